@@ -9,20 +9,20 @@ fn main() -> Result<()> {
     } else {
         let file_name = args.next().unwrap();
         let key = args.next().unwrap();
-        let key = if key.starts_with("0x") {
-            u32::from_str_radix(&key, 16)
+        let key = if let Some(hex) = key.strip_prefix("0x") {
+            u32::from_str_radix(hex, 16)
                 .wrap_err("can't parse hex key!")
                 .unwrap()
         } else {
-            u32::from_str_radix(&key, 10)
-                .wrap_err("can't parse dec key!")
-                .unwrap()
+            key.parse::<u32>().wrap_err("can't parse dec key!").unwrap()
         };
-        let data = std::fs::read(&file_name)
+        #[cfg(feature = "mmap")]
+        let data = osaka_sim_re::mmap::decrypt_file(&file_name, key)
             .wrap_err("can't read file!")
             .unwrap();
-        let data = osaka_sim_re::bin::decrpyt(&data, key)
-            .wrap_err("error decrypting file!")
+        #[cfg(not(feature = "mmap"))]
+        let data = osaka_sim_re::bin::decrypt_file(&file_name, key)
+            .wrap_err("can't read file!")
             .unwrap();
         std::fs::write(file_name + ".dec", data)
             .wrap_err("error writing decrypted file!")
@@ -1,4 +1,7 @@
 use color_eyre::{eyre::Context, Report, Result};
+use osaka_sim_re::bin::{sniff, DecryptError, FileKind};
+use osaka_sim_re::name::Name;
+use osaka_sim_re::pak::{Clash, Collision, OutputDir, PakArchive, PakEntry};
 use pelite::pattern;
 use pelite::pe32::*;
 
@@ -8,6 +11,12 @@ fn main() -> Result<()> {
         Err(Report::msg("Not enough arguments!"))
     } else {
         let file_name = args.next().unwrap();
+        // what to do with names that are already taken, renames by default
+        let policy = match args.next().as_deref() {
+            Some("--overwrite") => Collision::Overwrite,
+            Some("--skip") => Collision::Skip,
+            _ => Collision::Rename,
+        };
         let parent = std::path::Path::new(&file_name).parent().unwrap();
         let file_data = std::fs::read(&file_name)
             .wrap_err("can't read file!")
@@ -34,21 +43,22 @@ fn main() -> Result<()> {
             // eprintln!("{:X?}", save);
             let model_path = parent.join("model").join(MODEL_FOLDERS[i as usize]);
             std::fs::create_dir_all(&model_path)?;
-            let fname = pe.derva_c_str(save[1]).unwrap().to_str().unwrap();
+            let fname = read_name(pe, save[1]);
             let key = save[2];
             let files_rva = save[3];
 
-            let pak = std::fs::read(parent.join(fname))
-                .wrap_err("error reading model file!")
-                .unwrap();
-            let pak = osaka_sim_re::bin::decrpyt(&pak, key)
-                .wrap_err("error decrypting model file!")
-                .unwrap();
+            let pak = open_pak(
+                parent.join(fname.as_str()),
+                key,
+                read_entries(pe, files_rva),
+            )
+            .wrap_err("error reading model file!")
+            .unwrap();
 
-            dump_files(files_rva, pe, &pak, &model_path, ".hgm")?;
+            dump_files(&pak, OutputDir::new(&model_path, policy), FileKind::Hgm)?;
 
             // textures?
-            const TEXTURE_DATA: [(&'static str, u32, u32); 5] = [
+            const TEXTURE_DATA: [(&str, u32, u32); 5] = [
                 ("model/texture00.bin", 0x83D9DB43, 0xE3720),
                 ("model/texture01.bin", 0xFE6725D1, 0xE38B8),
                 ("model/texture02.bin", 0x75893254, 0xE3E98),
@@ -56,13 +66,10 @@ fn main() -> Result<()> {
                 ("model/texture04.bin", 0x98D57FFC, 0xE3F38),
             ];
             let (fname, key, files_rva) = TEXTURE_DATA[i as usize];
-            let pak = std::fs::read(parent.join(fname))
+            let pak = open_pak(parent.join(fname), key, read_entries(pe, files_rva))
                 .wrap_err("error reading texture file!")
                 .unwrap();
-            let pak = osaka_sim_re::bin::decrpyt(&pak, key)
-                .wrap_err("error decrypting texture file!")
-                .unwrap();
-            dump_files(files_rva, pe, &pak, &model_path, ".tga")?;
+            dump_files(&pak, OutputDir::new(&model_path, policy), FileKind::Tga)?;
         }
 
         for i in 0..4 {
@@ -81,18 +88,19 @@ fn main() -> Result<()> {
             // eprintln!("{:X?}", save);
             let animation_path = parent.join("animation").join(ANIMATION_FOLDERS[i as usize]);
             std::fs::create_dir_all(&animation_path)?;
-            let fname = pe.derva_c_str(save[1]).unwrap().to_str().unwrap();
+            let fname = read_name(pe, save[1]);
             let key = save[2];
             let files_rva = save[3];
 
-            let pak = std::fs::read(parent.join(fname))
-                .wrap_err("error reading animation file!")
-                .unwrap();
-            let pak = osaka_sim_re::bin::decrpyt(&pak, key)
-                .wrap_err("error decrypting animation file!")
-                .unwrap();
+            let pak = open_pak(
+                parent.join(fname.as_str()),
+                key,
+                read_entries(pe, files_rva),
+            )
+            .wrap_err("error reading animation file!")
+            .unwrap();
 
-            dump_files(files_rva, pe, &pak, &animation_path, ".hga")?;
+            dump_files(&pak, OutputDir::new(&animation_path, policy), FileKind::Hga)?;
         }
 
         {
@@ -107,18 +115,19 @@ fn main() -> Result<()> {
             );
             // eprintln!("{:X?}", save);
             let clipper_path = parent.join("clipper");
-            let fname = pe.derva_c_str(save[1]).unwrap().to_str().unwrap();
+            let fname = read_name(pe, save[1]);
             let key = save[2];
             let files_rva = save[3];
 
-            let pak = std::fs::read(parent.join(fname))
-                .wrap_err("error reading clipper file!")
-                .unwrap();
-            let pak = osaka_sim_re::bin::decrpyt(&pak, key)
-                .wrap_err("error decrypting clipper file!")
-                .unwrap();
+            let pak = open_pak(
+                parent.join(fname.as_str()),
+                key,
+                read_entries(pe, files_rva),
+            )
+            .wrap_err("error reading clipper file!")
+            .unwrap();
 
-            dump_files(files_rva, pe, &pak, &clipper_path, ".bmp")?;
+            dump_files(&pak, OutputDir::new(&clipper_path, policy), FileKind::Bmp)?;
         }
 
         for i in 0..2 {
@@ -134,33 +143,34 @@ fn main() -> Result<()> {
             );
             let sound_path = parent.join("sound");
             std::fs::create_dir_all(&sound_path)?;
-            let fname = pe.derva_c_str(save[1]).unwrap().to_str().unwrap();
+            let fname = read_name(pe, save[1]);
             let key = save[2];
             let files_rva = save[3];
 
-            let pak = std::fs::read(parent.join(fname))
-                .wrap_err("error reading sound file!")
-                .unwrap();
-            let pak = osaka_sim_re::bin::decrpyt(&pak, key)
-                .wrap_err("error decrypting sound file!")
-                .unwrap();
+            let pak = open_pak(
+                parent.join(fname.as_str()),
+                key,
+                read_entries(pe, files_rva),
+            )
+            .wrap_err("error reading sound file!")
+            .unwrap();
 
-            dump_files(files_rva, pe, &pak, &sound_path, ".wav")?;
+            dump_files(&pak, OutputDir::new(&sound_path, policy), FileKind::Wav)?;
         }
 
         Ok(())
     }
 }
 
-fn dump_files(
-    files_rva: u32,
-    pe: PeFile,
-    pak: &[u8],
-    model_path: &std::path::PathBuf,
-    ext: &str,
-) -> Result<(), Report> {
+// Names are CP932 in the executable
+fn read_name(pe: PeFile, rva: u32) -> Name<'static> {
+    Name::decode(pe.derva_c_str(rva).unwrap().as_ref()).into_owned()
+}
+
+fn read_entries(pe: PeFile, files_rva: u32) -> Vec<PakEntry> {
     let mut save = [0u32; 4];
-    Ok(for file_rva in (files_rva..).step_by(4 * 3) {
+    let mut ret = Vec::new();
+    for file_rva in (files_rva..).step_by(4 * 3) {
         if !pe
             .scanner()
             .exec(file_rva, pattern!("u4 u4 *{'}"), &mut save)
@@ -170,11 +180,67 @@ fn dump_files(
         if save[2] == 0 {
             break;
         }
-        let start = save[1] as usize;
-        let len = save[2] as usize;
-        let name = pe.derva_c_str(save[3]).unwrap().to_str().unwrap();
+        ret.push(PakEntry {
+            name: read_name(pe, save[3]),
+            offset: save[1] as usize,
+            len: save[2] as usize,
+        });
+    }
+    ret
+}
 
-        let file_data = &pak[start..start + len];
-        std::fs::write(model_path.join(format!("{name}{ext}")), file_data)?;
-    })
+// Mapped archives only keep the entry being written in memory
+fn open_pak(
+    path: std::path::PathBuf,
+    key: u32,
+    entries: Vec<PakEntry>,
+) -> Result<PakArchive<impl AsRef<[u8]>>, DecryptError> {
+    #[cfg(feature = "mmap")]
+    return PakArchive::open_mmap(path, key, entries);
+    #[cfg(not(feature = "mmap"))]
+    PakArchive::open(path, key, entries)
+}
+
+fn dump_files<D: AsRef<[u8]>>(
+    pak: &PakArchive<D>,
+    mut out: OutputDir,
+    expected: FileKind,
+) -> Result<(), Report> {
+    if !pak.key_looks_valid() {
+        eprintln!(
+            "key 0x{:08X} doesn't decrypt anything recognisable!",
+            pak.key()
+        );
+    }
+    let mut file_data = Vec::new();
+    for entry in pak.entries() {
+        pak.read_into(entry, &mut file_data)?;
+        let kind = sniff(&file_data);
+        match kind {
+            FileKind::Unknown => eprintln!("{}: unrecognised data", entry.name),
+            _ if kind != expected => eprintln!("{}: looks like {:?}", entry.name, kind),
+            _ => {}
+        }
+        if let Err(e) = kind.check(&file_data) {
+            eprintln!("{}: {}", entry.name, e);
+        } else if kind == FileKind::Wav {
+            if let Err(e) = osaka_sim_re::sound::validate(&file_data) {
+                eprintln!("{}: {}", entry.name, e);
+            }
+        }
+        let ext = kind.extension().or(expected.extension()).unwrap();
+        let placement = out.place(&entry.name, ext)?;
+        match (placement.clash, &placement.path) {
+            (Some(Clash::Duplicate), Some(path)) => {
+                eprintln!("{}: duplicate name, writing {}", entry.name, path.display())
+            }
+            (Some(Clash::Duplicate), None) => eprintln!("{}: duplicate name, skipped", entry.name),
+            (Some(Clash::Exists), None) => eprintln!("{}: already extracted, skipped", entry.name),
+            _ => {}
+        }
+        if let Some(path) = placement.path {
+            std::fs::write(path, &file_data)?;
+        }
+    }
+    Ok(())
 }
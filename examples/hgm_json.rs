@@ -0,0 +1,22 @@
+use color_eyre::eyre::{Report, Result, WrapErr};
+use osaka_sim_re::hg::{read_blocks, write_blocks, Block};
+
+// model.hgm -> model.hgm.json and back again
+fn main() -> Result<()> {
+    color_eyre::install()?;
+
+    let mut args = std::env::args().skip(1);
+    let Some(file_name) = args.next() else {
+        return Err(Report::msg("Not enough arguments!"));
+    };
+    let data = std::fs::read(&file_name).wrap_err("can't read file!")?;
+    if let Some(out) = file_name.strip_suffix(".json") {
+        let blocks: Vec<Block> = serde_json::from_slice(&data).wrap_err("can't parse json!")?;
+        std::fs::write(out, write_blocks(&blocks)?).wrap_err("can't write hgm!")?;
+    } else {
+        let blocks = read_blocks(&data)?;
+        let json = serde_json::to_string_pretty(&blocks)?;
+        std::fs::write(file_name + ".json", json).wrap_err("can't write json!")?;
+    }
+    Ok(())
+}
@@ -0,0 +1,62 @@
+use color_eyre::eyre::{Report, Result, WrapErr};
+use osaka_sim_re::convert::Conversion;
+use osaka_sim_re::hg::strip::{stripify, StripStats};
+use osaka_sim_re::hg::{write_blocks, Block};
+use osaka_sim_re::import::{self, ImportOptions};
+
+fn main() -> Result<()> {
+    color_eyre::install()?;
+
+    let mut args = std::env::args().skip(1);
+    let Some(file_name) = args.next() else {
+        return Err(Report::msg("Not enough arguments!"));
+    };
+    let strip = args.any(|x| x == "--strip");
+    // stripped below so there's something to report
+    let options = ImportOptions {
+        strip: false,
+        source: if file_name.ends_with(".obj") {
+            Conversion::obj()
+        } else {
+            Conversion::gltf()
+        },
+    };
+
+    let data = std::fs::read(&file_name).wrap_err("can't read file!")?;
+    let meshes = if file_name.ends_with(".obj") {
+        import::read_obj(&String::from_utf8_lossy(&data))?
+    } else {
+        #[cfg(feature = "gltf")]
+        {
+            let base = std::path::Path::new(&file_name).parent();
+            import::read_gltf(&data, base)?
+        }
+        #[cfg(not(feature = "gltf"))]
+        return Err(Report::msg("glTF needs the `gltf` feature"));
+    };
+
+    let mut stats = StripStats::default();
+    let mut geometry = Vec::new();
+    for mesh in &meshes {
+        geometry.extend(mesh.to_geometry(&options)?);
+    }
+    let blocks: Vec<Block> = geometry
+        .into_iter()
+        .map(|mut g| {
+            if strip {
+                for group in g.idk.iter_mut() {
+                    let (stripped, s) = stripify(group);
+                    *group = stripped;
+                    stats += s;
+                }
+            }
+            Block::Geometry(g)
+        })
+        .collect();
+    if strip {
+        println!("{stats}");
+    }
+    println!("{} meshes, {} geometry blocks", meshes.len(), blocks.len());
+    std::fs::write(file_name + ".hgm", write_blocks(&blocks)?).wrap_err("can't write hgm!")?;
+    Ok(())
+}
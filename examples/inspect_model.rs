@@ -0,0 +1,82 @@
+use color_eyre::{eyre::Context, Report, Result};
+use osaka_sim_re::hg::{Block, TRS3d};
+use osaka_sim_re::le;
+use osaka_sim_re::math::{self, EulerOrder, Mat4};
+use osaka_sim_re::model::{Hierarchy, Model};
+
+fn difference(a: &Mat4, b: &Mat4) -> f32 {
+    a.as_flattened()
+        .iter()
+        .zip(b.as_flattened())
+        .map(|(x, y)| (x - y).abs())
+        .fold(0.0, f32::max)
+}
+
+// Prints what's needed to work out the parts of hgm files that are still guesses
+fn main() -> Result<()> {
+    color_eyre::install()?;
+
+    let args = std::env::args().skip(1);
+    if args.len() < 1 {
+        return Err(Report::msg("Not enough arguments!"));
+    }
+    for file_name in args {
+        let data = std::fs::read(&file_name).wrap_err("can't read file!")?;
+        let model = Model::read(&data).wrap_err("can't parse model!")?;
+        println!("{file_name}");
+
+        // Is `idk` a parent index? Then it's in range, u32::MAX for roots and never loops
+        let node_idk: Vec<u32> = model.nodes.iter().map(|x| x.transform.idk).collect();
+        let bone_idk: Vec<u32> = model.skeleton.iter().map(|x| x.block.idk).collect();
+        for (what, idk) in [("node", node_idk), ("bone", bone_idk)] {
+            if idk.is_empty() {
+                continue;
+            }
+            println!("  {what} idk: {idk:?}");
+            match Hierarchy::parents_from_indices(what, idk) {
+                Ok(_) => println!("  {what} idk works as parent indices"),
+                Err(e) => println!("  {what} idk isn't a parent index: {e}"),
+            }
+        }
+
+        // If a bone's coords are position, rotation and scale, one Euler order and unit should
+        // rebuild its matrix (or the inverse, if that's an offset matrix) for every bone
+        for bone in &model.skeleton {
+            let [pos, rot, scale] = bone.block.coords;
+            let stored = bone.block.to_matrix();
+            let inverse = bone.block.inverse().unwrap_or(stored);
+            let mut best = (f32::INFINITY, EulerOrder::default(), "", "");
+            for order in EulerOrder::ALL {
+                for (unit, rot) in [("radians", rot), ("degrees", rot.map(f32::to_radians))] {
+                    let trs = TRS3d { pos, rot, scale };
+                    let m = trs.to_matrix(order);
+                    for (which, target) in [("matrix", &stored), ("inverse", &inverse)] {
+                        let d = difference(&m, target);
+                        if d < best.0 {
+                            best = (d, order, unit, which);
+                        }
+                    }
+                }
+            }
+            let (d, order, unit, which) = best;
+            println!(
+                "  bone {}: closest {order:?} in {unit} vs {which}, off by {d}",
+                bone.block.name
+            );
+            if math::inverse(&stored).is_none() {
+                println!("  bone {}: singular matrix", bone.block.name);
+            }
+        }
+
+        // Raw hierarchy blocks, as u32s since that's what most of these files are made of
+        for block in &model.other {
+            if let Block::Raw(raw) = block {
+                if raw.typ == 8 {
+                    let words: Vec<u32> = le::read_all(&raw.data).collect();
+                    println!("  hierarchy block, {} bytes: {words:?}", raw.data.len());
+                }
+            }
+        }
+    }
+    Ok(())
+}
@@ -1,7 +1,11 @@
 use std::io::prelude::*;
 
 use color_eyre::{eyre::Context, Report, Result};
-use osaka_sim_re::hg::{Block, PTEnum, VertexFeatures};
+use osaka_sim_re::convert::Conversion;
+use osaka_sim_re::hg::VertexFeatures;
+use osaka_sim_re::le;
+use osaka_sim_re::math::EulerOrder;
+use osaka_sim_re::model::Model;
 
 fn main() -> Result<()> {
     color_eyre::install()?;
@@ -14,67 +18,60 @@ fn main() -> Result<()> {
         let data = std::fs::read(&file_name)
             .wrap_err("can't read file!")
             .unwrap();
-        let blocks = osaka_sim_re::hg::read_blocks(&data);
-        println!("{:#?}", blocks);
+        let model = Model::read(&data).wrap_err("can't parse model!")?;
+        println!("{:#?}", model);
+        for mesh in &model.meshes {
+            if let Err(e) = mesh.geometry.check_bounds() {
+                eprintln!("{}: {e}", mesh.geometry.name);
+            }
+        }
+        if let Some(bounds) = model.bounds(EulerOrder::default()) {
+            println!("bounds {:?} .. {:?}", bounds.min, bounds.max);
+        } else if let Some(bounds) = model.untransformed_bounds() {
+            println!("untransformed bounds {:?} .. {:?}", bounds.min, bounds.max);
+        }
         // writes obj files?
-        for block in blocks {
-            if let Block::Geometry(g) = block {
-                let vertex_count = g.vertex_num.unwrap();
-                let vertex_stride = g.vertex_size.unwrap();
-                let vertex_data = g.vertex_data.unwrap();
+        for mesh in model.meshes {
+            // positions and winding the way OBJ wants them
+            let g = Conversion::obj().geometry(&mesh.geometry);
+            // bool6 blocks have no vertices at all
+            let (Some(vertex_count), Some(vertex_stride), Some(vertex_data)) =
+                (g.vertex_num, g.stride(), g.vertex_data.as_deref())
+            else {
+                eprintln!("{}: no vertex data, skipped", g.name);
+                continue;
+            };
 
-                let out = std::fs::File::create(file_name.clone() + "_" + g.name + ".obj").unwrap();
-                let mut f = std::io::BufWriter::new(out);
-                #[allow(unused_assignments)]
-                for i in 0..vertex_count as usize {
-                    let mut c = vertex_stride as usize * i;
-                    if g.vertex_bitmask.contains(VertexFeatures::Position) {
-                        let xyz = bytemuck::pod_read_unaligned::<[f32; 3]>(&vertex_data[c..c + 12]);
-                        write!(f, "v {} {} {}\n", xyz[0], xyz[1], xyz[2])?;
-                        c += 12;
-                    }
-                    if g.vertex_bitmask.contains(VertexFeatures::Normal) {
-                        let norm =
-                            bytemuck::pod_read_unaligned::<[f32; 3]>(&vertex_data[c..c + 12]);
-                        write!(f, "vn {} {} {}\n", norm[0], norm[1], norm[2])?;
-                        c += 12;
-                    }
-                    // if g.vertex_bitmask.contains(VertexFeatures::Tangent | VertexFeatures::Binormal) {
-                    //     todo!("{}", g.vertex_bitmask)
-                    // }
-                    // if g.vertex_bitmask.contains(VertexFeatures::Color0) {
-                    //     c += 12;
-                    // }
-                    // if g.vertex_bitmask.contains(VertexFeatures::Color1) {
-                    //     c += 12;
-                    // }
+            let out = std::fs::File::create(file_name.clone() + "_" + &g.name.file_name() + ".obj")
+                .unwrap();
+            let mut f = std::io::BufWriter::new(out);
+            #[allow(unused_assignments)]
+            for i in 0..vertex_count as usize {
+                let mut c = vertex_stride * i;
+                if g.vertex_bitmask.contains(VertexFeatures::Position) {
+                    let xyz = le::read::<[f32; 3]>(vertex_data, c);
+                    writeln!(f, "v {} {} {}", xyz[0], xyz[1], xyz[2])?;
+                    c += 12;
                 }
-                if g.idk.len() > 1 {
-                    todo!()
+                if g.vertex_bitmask.contains(VertexFeatures::Normal) {
+                    let norm = le::read::<[f32; 3]>(vertex_data, c);
+                    writeln!(f, "vn {} {} {}", norm[0], norm[1], norm[2])?;
+                    c += 12;
                 }
-                let idxs = &g.idk[0];
-                if idxs.typ != PTEnum::TriangleStrip {
-                    todo!("{:?}", idxs.typ);
-                }
-                write!(f, "\n")?;
-                for i in 0..idxs.words.len() - 2 {
-                    if i & 1 != 0 {
-                        write!(
-                            f,
-                            "f {} {} {}\n",
-                            idxs.words[i + 0] + 1,
-                            idxs.words[i + 1] + 1,
-                            idxs.words[i + 2] + 1
-                        )?;
-                    } else {
-                        write!(
-                            f,
-                            "f {} {} {}\n",
-                            idxs.words[i + 0] + 1,
-                            idxs.words[i + 2] + 1,
-                            idxs.words[i + 1] + 1
-                        )?;
-                    }
+                // if g.vertex_bitmask.contains(VertexFeatures::Tangent | VertexFeatures::Binormal) {
+                //     todo!("{}", g.vertex_bitmask)
+                // }
+                // if g.vertex_bitmask.contains(VertexFeatures::Color0) {
+                //     c += 12;
+                // }
+                // if g.vertex_bitmask.contains(VertexFeatures::Color1) {
+                //     c += 12;
+                // }
+            }
+            writeln!(f)?;
+            for group in &g.idk {
+                for [a, b, c] in group.triangles() {
+                    writeln!(f, "f {} {} {}", a + 1, b + 1, c + 1)?;
                 }
             }
         }
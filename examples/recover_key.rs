@@ -0,0 +1,26 @@
+use color_eyre::eyre::{Report, Result, WrapErr};
+use osaka_sim_re::bin::{recover_key, Hint};
+
+fn main() -> Result<()> {
+    color_eyre::install()?;
+
+    let mut args = std::env::args().skip(1);
+    if args.len() < 1 {
+        Err(Report::msg("Not enough arguments!"))
+    } else {
+        let file_name = args.next().unwrap();
+        let hints = match args.next().as_deref() {
+            None => Hint::all().to_vec(),
+            Some("hgm") => vec![Hint::hgm()],
+            Some("tga") => vec![Hint::tga(), Hint::tga_mapped()],
+            Some("wav") => vec![Hint::wav()],
+            Some("bmp") => vec![Hint::bmp()],
+            Some(x) => return Err(Report::msg(format!("unknown hint {x}!"))),
+        };
+        let data = std::fs::read(&file_name).wrap_err("can't read file!")?;
+        for candidate in recover_key(&data, &hints).iter().take(8) {
+            println!("0x{:08X} {:.4}", candidate.key, candidate.score);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,40 @@
+use color_eyre::{Report, Result};
+use osaka_sim_re::bin::{sniff, FileKind};
+use osaka_sim_re::hg::{read_blocks, write_blocks};
+use osaka_sim_re::model::Model;
+use osaka_sim_re::texture::{bmp, tga};
+
+fn main() -> Result<()> {
+    color_eyre::install()?;
+
+    let args = std::env::args().skip(1);
+    if args.len() < 1 {
+        return Err(Report::msg("Not enough arguments!"));
+    }
+    for file_name in args {
+        let data = std::fs::read(&file_name)?;
+        let result = match sniff(&data) {
+            FileKind::Tga => tga::verify_roundtrip(&data),
+            FileKind::Bmp => bmp::verify_roundtrip(&data),
+            FileKind::Hgm => {
+                if write_blocks(&read_blocks(&data)?)? != data {
+                    println!("{file_name}: re-written model differs");
+                } else if write_blocks(&Model::read(&data)?.into_blocks())? != data {
+                    println!("{file_name}: model re-assembled out of order");
+                } else {
+                    println!("{file_name}: ok");
+                }
+                continue;
+            }
+            x => {
+                println!("{file_name}: can't re-encode {x:?}");
+                continue;
+            }
+        };
+        match result {
+            Ok(()) => println!("{file_name}: ok"),
+            Err(e) => println!("{file_name}: {e}"),
+        }
+    }
+    Ok(())
+}
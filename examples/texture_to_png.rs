@@ -0,0 +1,25 @@
+use color_eyre::eyre::{Report, Result, WrapErr};
+use osaka_sim_re::bin::{sniff, FileKind};
+use osaka_sim_re::texture::{bmp, tga};
+
+fn main() -> Result<()> {
+    color_eyre::install()?;
+
+    let mut args = std::env::args().skip(1);
+    if args.len() < 1 {
+        Err(Report::msg("Not enough arguments!"))
+    } else {
+        let file_name = args.next().unwrap();
+        let data = std::fs::read(&file_name).wrap_err("can't read file!")?;
+        let image = match sniff(&data) {
+            FileKind::Tga => tga::decode(&data).wrap_err("can't decode tga!")?,
+            FileKind::Bmp => bmp::decode(&data).wrap_err("can't decode bmp!")?,
+            x => return Err(Report::msg(format!("not a texture: {x:?}"))),
+        };
+        let out = std::fs::File::create(file_name + ".png").wrap_err("can't create png!")?;
+        image
+            .write_png(std::io::BufWriter::new(out))
+            .wrap_err("can't write png!")?;
+        Ok(())
+    }
+}
@@ -0,0 +1,358 @@
+// Evaluating clips only: the animation blocks of .hga files (5 to 7) aren't decoded, so clips
+// have to be put together by hand or by whatever ends up decoding them
+
+use crate::math::{self, Mat4, Quat};
+use crate::skin::{Pose, Skeleton, SkinError};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Transform {
+    pub translation: [f32; 3],
+    pub rotation: Quat,
+    pub scale: [f32; 3],
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self {
+            translation: [0.0; 3],
+            rotation: math::QUAT_IDENTITY,
+            scale: [1.0; 3],
+        }
+    }
+}
+
+impl Transform {
+    pub fn to_matrix(&self) -> Mat4 {
+        math::compose(self.translation, self.rotation, self.scale)
+    }
+
+    pub fn lerp(&self, other: &Self, t: f32) -> Self {
+        Self {
+            translation: math::lerp3(self.translation, other.translation, t),
+            rotation: math::slerp(self.rotation, other.rotation, t),
+            scale: math::lerp3(self.scale, other.scale, t),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Keyframe {
+    pub time: f32,
+    pub transform: Transform,
+}
+
+/// Keys for one bone or transform, sorted by time.
+#[derive(Debug, Clone, Default)]
+pub struct Track {
+    pub target: String,
+    pub keys: Vec<Keyframe>,
+}
+
+impl Track {
+    /// None without keys or for a NaN `t`, infinities clamp to the ends.
+    pub fn sample(&self, t: f32) -> Option<Transform> {
+        let first = self.keys.first()?;
+        let last = self.keys.last()?;
+        if t.is_nan() {
+            return None;
+        }
+        if t <= first.time {
+            return Some(first.transform);
+        }
+        if t >= last.time {
+            return Some(last.transform);
+        }
+        // first key after t, only 0 or past the end if the keys aren't sorted
+        let i = self
+            .keys
+            .partition_point(|x| x.time <= t)
+            .clamp(1, self.keys.len() - 1);
+        let (a, b) = (&self.keys[i - 1], &self.keys[i]);
+        let span = b.time - a.time;
+        if span <= 0.0 {
+            return Some(b.transform);
+        }
+        Some(a.transform.lerp(&b.transform, (t - a.time) / span))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Wrap {
+    #[default]
+    Loop,
+    Clamp,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Clip {
+    pub name: String,
+    // seconds
+    pub duration: f32,
+    pub tracks: Vec<Track>,
+}
+
+impl Clip {
+    /// NaN is the start, infinities clamp to the ends whatever `wrap` is.
+    pub fn local_time(&self, t: f32, wrap: Wrap) -> f32 {
+        if self.duration.is_nan() || self.duration <= 0.0 || t.is_nan() {
+            return 0.0;
+        }
+        if t.is_infinite() {
+            return t.clamp(0.0, self.duration);
+        }
+        match wrap {
+            Wrap::Loop => t.rem_euclid(self.duration),
+            Wrap::Clamp => t.clamp(0.0, self.duration),
+        }
+    }
+
+    pub fn track(&self, target: &str) -> Option<&Track> {
+        self.tracks.iter().find(|x| x.target == target)
+    }
+
+    /// For transform nodes or anything else that isn't part of a skeleton.
+    pub fn sample_target(&self, target: &str, t: f32, wrap: Wrap) -> Option<Transform> {
+        self.track(target)?.sample(self.local_time(t, wrap))
+    }
+
+    /// Local transform of every joint, the ones without a track keep `base`.
+    pub fn sample_local(
+        &self,
+        skeleton: &Skeleton,
+        base: &[Transform],
+        t: f32,
+        wrap: Wrap,
+    ) -> Vec<Transform> {
+        let t = self.local_time(t, wrap);
+        skeleton
+            .joints
+            .iter()
+            .enumerate()
+            .map(|(i, joint)| {
+                self.track(&joint.name)
+                    .and_then(|x| x.sample(t))
+                    .or_else(|| base.get(i).copied())
+                    .unwrap_or_default()
+            })
+            .collect()
+    }
+}
+
+/// Local transforms per joint and the world matrices they chain up to.
+#[derive(Debug, Clone)]
+pub struct AnimPose {
+    pub local: Vec<Transform>,
+    // feeds `skin::skin`
+    pub pose: Pose,
+}
+
+impl AnimPose {
+    pub fn from_local(skeleton: &Skeleton, local: Vec<Transform>) -> Result<Self, SkinError> {
+        let matrices: Vec<Mat4> = local.iter().map(Transform::to_matrix).collect();
+        let pose = skeleton.pose_from_local(&matrices)?;
+        Ok(Self { local, pose })
+    }
+}
+
+pub fn sample(
+    skeleton: &Skeleton,
+    base: &[Transform],
+    clip: &Clip,
+    t: f32,
+    wrap: Wrap,
+) -> Result<AnimPose, SkinError> {
+    AnimPose::from_local(skeleton, clip.sample_local(skeleton, base, t, wrap))
+}
+
+/// Samples both clips at their own time and mixes the local transforms, `weight` 0 is all `a`.
+pub fn sample_blend(
+    skeleton: &Skeleton,
+    base: &[Transform],
+    (a, ta): (&Clip, f32),
+    (b, tb): (&Clip, f32),
+    weight: f32,
+    wrap: Wrap,
+) -> Result<AnimPose, SkinError> {
+    let local = a
+        .sample_local(skeleton, base, ta, wrap)
+        .iter()
+        .zip(b.sample_local(skeleton, base, tb, wrap))
+        .map(|(x, y)| x.lerp(&y, weight))
+        .collect();
+    AnimPose::from_local(skeleton, local)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::skin::Joint;
+
+    // quarter turn around z
+    const TURN: Quat = [
+        0.0,
+        0.0,
+        std::f32::consts::FRAC_1_SQRT_2,
+        std::f32::consts::FRAC_1_SQRT_2,
+    ];
+
+    fn key(time: f32, x: f32, rotation: Quat) -> Keyframe {
+        Keyframe {
+            time,
+            transform: Transform {
+                translation: [x, 0.0, 0.0],
+                rotation,
+                scale: [1.0; 3],
+            },
+        }
+    }
+
+    fn track(target: &str) -> Track {
+        Track {
+            target: target.to_string(),
+            keys: vec![
+                key(0.0, 0.0, math::QUAT_IDENTITY),
+                key(1.0, 2.0, TURN),
+                key(2.0, 4.0, TURN),
+            ],
+        }
+    }
+
+    fn close(a: &[f32], b: &[f32]) -> bool {
+        a.len() == b.len() && a.iter().zip(b).all(|(x, y)| (x - y).abs() < 1e-5)
+    }
+
+    #[test]
+    fn samples_between_keys() {
+        let track = track("root");
+        let half = track.sample(0.5).unwrap();
+        assert!(close(&half.translation, &[1.0, 0.0, 0.0]));
+        // eighth turn
+        let s = (std::f32::consts::PI / 8.0).sin();
+        let c = (std::f32::consts::PI / 8.0).cos();
+        assert!(close(&half.rotation, &[0.0, 0.0, s, c]));
+        let at_key = track.sample(1.0).unwrap();
+        assert!(close(&at_key.translation, &[2.0, 0.0, 0.0]));
+        assert!(close(&at_key.rotation, &TURN));
+        assert!(close(
+            &track.sample(1.5).unwrap().translation,
+            &[3.0, 0.0, 0.0]
+        ));
+
+        assert_eq!(track.sample(-1.0).unwrap(), track.keys[0].transform);
+        assert_eq!(track.sample(5.0).unwrap(), track.keys[2].transform);
+        assert_eq!(
+            track.sample(f32::INFINITY).unwrap(),
+            track.keys[2].transform
+        );
+        assert_eq!(
+            track.sample(f32::NEG_INFINITY).unwrap(),
+            track.keys[0].transform
+        );
+        assert!(track.sample(f32::NAN).is_none());
+        assert!(Track::default().sample(0.0).is_none());
+    }
+
+    #[test]
+    fn unsorted_keys_dont_panic() {
+        let mut track = track("root");
+        track.keys.reverse();
+        track.keys[1].time = f32::NAN;
+        for t in [0.0, 0.5, 1.0, 1.5, 2.0] {
+            track.sample(t);
+        }
+    }
+
+    #[test]
+    fn wraps_time() {
+        let clip = Clip {
+            name: "walk".to_string(),
+            duration: 2.0,
+            tracks: vec![track("root")],
+        };
+        assert_eq!(clip.local_time(2.5, Wrap::Loop), 0.5);
+        assert_eq!(clip.local_time(-0.5, Wrap::Loop), 1.5);
+        assert_eq!(clip.local_time(2.5, Wrap::Clamp), 2.0);
+        assert_eq!(clip.local_time(f32::NAN, Wrap::Loop), 0.0);
+        assert_eq!(clip.local_time(f32::INFINITY, Wrap::Loop), 2.0);
+        assert_eq!(clip.local_time(f32::NEG_INFINITY, Wrap::Clamp), 0.0);
+        let translation = |t| {
+            clip.sample_target("root", t, Wrap::Loop)
+                .unwrap()
+                .translation
+        };
+        assert!(close(&translation(2.5), &translation(0.5)));
+        assert!(clip.sample_target("nobody", 0.5, Wrap::Loop).is_none());
+    }
+
+    fn skeleton() -> Skeleton {
+        let joint = |name: &str| Joint {
+            name: name.to_string(),
+            inverse_bind: math::IDENTITY,
+            bind: math::IDENTITY,
+        };
+        Skeleton {
+            joints: vec![joint("root"), joint("arm")],
+            parents: Some(vec![None, Some(0)]),
+        }
+    }
+
+    #[test]
+    fn samples_poses() {
+        let skeleton = skeleton();
+        let clip = Clip {
+            name: "move".to_string(),
+            duration: 2.0,
+            tracks: vec![track("root")],
+        };
+        let base = [
+            Transform::default(),
+            key(0.0, 1.0, math::QUAT_IDENTITY).transform,
+        ];
+        let pose = sample(&skeleton, &base, &clip, 1.0, Wrap::Clamp).unwrap();
+        // the arm keeps its base transform
+        assert_eq!(pose.local[1], base[1]);
+        // root moved by 2 and turned a quarter, the arm's 1 along x ends up along y
+        let arm = pose.pose.world[1];
+        assert!(close(&arm[3], &[2.0, 1.0, 0.0, 1.0]));
+        let root = pose.pose.world[0];
+        assert!(close(&root[3], &[2.0, 0.0, 0.0, 1.0]));
+
+        let mut no_hierarchy = skeleton.clone();
+        no_hierarchy.parents = None;
+        assert!(matches!(
+            sample(&no_hierarchy, &base, &clip, 1.0, Wrap::Clamp),
+            Err(SkinError::NoHierarchy)
+        ));
+    }
+
+    #[test]
+    fn blends_clips() {
+        let skeleton = skeleton();
+        let still = Clip {
+            name: "still".to_string(),
+            duration: 2.0,
+            tracks: Vec::new(),
+        };
+        let moving = Clip {
+            name: "move".to_string(),
+            duration: 2.0,
+            tracks: vec![track("root")],
+        };
+        let base = [Transform::default(); 2];
+        let at = |weight| {
+            sample_blend(
+                &skeleton,
+                &base,
+                (&still, 0.0),
+                (&moving, 2.0),
+                weight,
+                Wrap::Clamp,
+            )
+            .unwrap()
+            .local[0]
+        };
+        assert_eq!(at(0.0), Transform::default());
+        assert!(close(&at(1.0).translation, &[4.0, 0.0, 0.0]));
+        assert!(close(&at(0.5).translation, &[2.0, 0.0, 0.0]));
+    }
+}
@@ -0,0 +1,324 @@
+use thiserror::Error;
+
+use crate::hg::{GeometryBlock, VertexFeatures};
+use crate::le;
+use crate::math::{self, EulerOrder, Mat4, Vec3};
+use crate::model::Model;
+
+// Stored spheres are f32 and were probably computed differently, allow a bit of slack.
+// Relative to the radius, or absolute for small ones
+pub const TOLERANCE: f32 = 1e-3;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum BoundsError {
+    #[error("geometry has no positions")]
+    NoPositions,
+    #[error("vertex {0} is {1} away from the center, stored radius is {2}")]
+    Outside(usize, f32, f32),
+    #[error("stored center {stored:?} is {distance} away from the computed {computed:?}")]
+    Center {
+        stored: Vec3,
+        computed: Vec3,
+        distance: f32,
+    },
+    #[error("stored radius {stored} differs from the computed {computed}")]
+    Radius { stored: f32, computed: f32 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    pub fn from_points(points: &[Vec3]) -> Option<Self> {
+        let (first, rest) = points.split_first()?;
+        let mut ret = Self {
+            min: *first,
+            max: *first,
+        };
+        for p in rest {
+            ret.extend(*p);
+        }
+        Some(ret)
+    }
+
+    pub fn extend(&mut self, p: Vec3) {
+        self.min = [0, 1, 2].map(|i| self.min[i].min(p[i]));
+        self.max = [0, 1, 2].map(|i| self.max[i].max(p[i]));
+    }
+
+    pub fn union(&self, other: &Self) -> Self {
+        let mut ret = *self;
+        ret.extend(other.min);
+        ret.extend(other.max);
+        ret
+    }
+
+    pub fn center(&self) -> Vec3 {
+        [0, 1, 2].map(|i| (self.min[i] + self.max[i]) / 2.0)
+    }
+
+    pub fn size(&self) -> Vec3 {
+        [0, 1, 2].map(|i| self.max[i] - self.min[i])
+    }
+
+    pub fn corners(&self) -> [Vec3; 8] {
+        [0, 1, 2, 3, 4, 5, 6, 7].map(|i| {
+            [0, 1, 2].map(|axis| {
+                if (i >> axis) & 1 == 0 {
+                    self.min[axis]
+                } else {
+                    self.max[axis]
+                }
+            })
+        })
+    }
+
+    /// Box around the transformed corners.
+    pub fn transform(&self, m: &Mat4) -> Self {
+        let corners = self.corners().map(|p| math::transform_point(m, p));
+        Self::from_points(&corners).unwrap_or(*self)
+    }
+}
+
+/// What `GeometryBlock::coords` looks like: center + radius.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sphere {
+    pub center: Vec3,
+    pub radius: f32,
+}
+
+impl Sphere {
+    pub fn from_coords([x, y, z, radius]: [f32; 4]) -> Self {
+        Self {
+            center: [x, y, z],
+            radius,
+        }
+    }
+
+    pub fn to_coords(&self) -> [f32; 4] {
+        let [x, y, z] = self.center;
+        [x, y, z, self.radius]
+    }
+
+    /// Centered on the bounding box, not the smallest sphere but close enough.
+    pub fn from_points(points: &[Vec3]) -> Option<Self> {
+        let center = Aabb::from_points(points)?.center();
+        let radius = points
+            .iter()
+            .map(|p| distance(*p, center))
+            .fold(0f32, f32::max);
+        Some(Self { center, radius })
+    }
+
+    pub fn contains(&self, p: Vec3) -> bool {
+        distance(p, self.center) <= self.radius * (1.0 + TOLERANCE) + TOLERANCE
+    }
+
+    pub fn to_aabb(&self) -> Aabb {
+        Aabb {
+            min: self.center.map(|x| x - self.radius),
+            max: self.center.map(|x| x + self.radius),
+        }
+    }
+}
+
+fn distance(a: Vec3, b: Vec3) -> f32 {
+    (0..3).map(|i| (a[i] - b[i]).powi(2)).sum::<f32>().sqrt()
+}
+
+impl GeometryBlock<'_> {
+    pub fn bound(&self) -> Sphere {
+        Sphere::from_coords(self.coords)
+    }
+
+    pub fn positions(&self) -> Option<Vec<Vec3>> {
+        let data = self.vertex_data.as_deref()?;
+        let at = self.vertex_bitmask.offset_of(VertexFeatures::Position)?;
+        let stride = self.stride()?;
+        if stride < at + 12 {
+            return None;
+        }
+        Some(
+            data.chunks_exact(stride)
+                .take(self.vertex_num.map_or(usize::MAX, |x| x as usize))
+                .map(|x| le::read(x, at))
+                .collect(),
+        )
+    }
+
+    /// Recomputed from the vertex data, ignores what's stored.
+    pub fn compute_bounds(&self) -> Option<(Aabb, Sphere)> {
+        let positions = self.positions()?;
+        Some((
+            Aabb::from_points(&positions)?,
+            Sphere::from_points(&positions)?,
+        ))
+    }
+
+    /// `check_bounds_with` the default `TOLERANCE`.
+    pub fn check_bounds(&self) -> Result<(), BoundsError> {
+        self.check_bounds_with(TOLERANCE)
+    }
+
+    /// Checks that the stored sphere holds every vertex and matches the one `compute_bounds`
+    /// gives, within `tolerance` times the radius (or at least `tolerance`).
+    pub fn check_bounds_with(&self, tolerance: f32) -> Result<(), BoundsError> {
+        let bound = self.bound();
+        let positions = self.positions().ok_or(BoundsError::NoPositions)?;
+        if let Some(i) = positions.iter().position(|p| !bound.contains(*p)) {
+            return Err(BoundsError::Outside(
+                i,
+                distance(positions[i], bound.center),
+                bound.radius,
+            ));
+        }
+        let computed = Sphere::from_points(&positions).ok_or(BoundsError::NoPositions)?;
+        let slack = tolerance * computed.radius.max(1.0);
+        let d = distance(bound.center, computed.center);
+        if d > slack {
+            return Err(BoundsError::Center {
+                stored: bound.center,
+                computed: computed.center,
+                distance: d,
+            });
+        }
+        if (bound.radius - computed.radius).abs() > slack {
+            return Err(BoundsError::Radius {
+                stored: bound.radius,
+                computed: computed.radius,
+            });
+        }
+        Ok(())
+    }
+}
+
+impl Model<'_> {
+    /// Node space to model space, following parents. None unless the model has a `Hierarchy`.
+    pub fn world_matrix(&self, node: usize, order: EulerOrder) -> Option<Mat4> {
+        let parents = &self.hierarchy()?.node_parents;
+        let mut ret = math::IDENTITY;
+        let mut current = Some(node);
+        // `Hierarchy` is checked for loops, this only stops a stale one going on forever
+        for _ in 0..=self.nodes.len() {
+            let Some(i) = current else {
+                break;
+            };
+            ret = math::mul(&ret, &self.nodes.get(i)?.transform.coords.to_matrix(order));
+            current = *parents.get(i)?;
+        }
+        Some(ret)
+    }
+
+    /// Mesh space to model space through the node the hierarchy places it under.
+    pub fn mesh_matrix(&self, mesh: usize, order: EulerOrder) -> Option<Mat4> {
+        match *self.hierarchy()?.mesh_nodes.get(mesh)? {
+            Some(node) => self.world_matrix(node, order),
+            None => Some(math::IDENTITY),
+        }
+    }
+
+    /// Every mesh in model space, from the stored spheres. Good for framing a camera.
+    /// None without a `Hierarchy`, see `untransformed_bounds`.
+    pub fn bounds(&self, order: EulerOrder) -> Option<Aabb> {
+        (0..self.meshes.len())
+            .map(|i| {
+                let bound = self.meshes[i].geometry.bound().to_aabb();
+                Some(bound.transform(&self.mesh_matrix(i, order)?))
+            })
+            .reduce(|a, b| Some(a?.union(&b?)))?
+    }
+
+    /// The stored spheres as they are, every mesh in its own space. Only right for models whose
+    /// meshes don't hang off transformed nodes.
+    pub fn untransformed_bounds(&self) -> Option<Aabb> {
+        self.meshes
+            .iter()
+            .map(|x| x.geometry.bound().to_aabb())
+            .reduce(|a, b| a.union(&b))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::Hierarchy;
+
+    const QUAD: &[u8] = include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/quad.hgm"));
+
+    fn close(a: &[f32], b: &[f32]) -> bool {
+        a.len() == b.len() && a.iter().zip(b).all(|(x, y)| (x - y).abs() < 1e-4)
+    }
+
+    #[test]
+    fn stored_sphere_is_compared() {
+        let model = Model::read(QUAD).unwrap();
+        let mut geometry = model.meshes[0].geometry.clone();
+        let (aabb, sphere) = geometry.compute_bounds().unwrap();
+        assert_eq!(aabb.min, [0.0; 3]);
+        assert_eq!(aabb.max, [1.0, 1.0, 0.0]);
+        assert!(close(&sphere.to_coords(), &[0.5, 0.5, 0.0, 0.5f32.sqrt()]));
+
+        // the fixture's radius is a bit too generous
+        assert!(matches!(
+            geometry.check_bounds(),
+            Err(BoundsError::Radius { stored: 0.75, .. })
+        ));
+        assert!(geometry.check_bounds_with(0.1).is_ok());
+
+        geometry.coords = sphere.to_coords();
+        assert_eq!(geometry.check_bounds(), Ok(()));
+        geometry.coords = [0.6, 0.5, 0.0, 1.0];
+        assert!(matches!(
+            geometry.check_bounds_with(0.05),
+            Err(BoundsError::Center { .. })
+        ));
+        geometry.coords = [0.5, 0.5, 0.0, 0.5];
+        assert!(matches!(
+            geometry.check_bounds(),
+            Err(BoundsError::Outside(0, ..))
+        ));
+        assert_eq!(
+            model.meshes[1].geometry.check_bounds(),
+            Err(BoundsError::NoPositions)
+        );
+    }
+
+    #[test]
+    fn world_space_needs_the_hierarchy() {
+        let mut model = Model::read(QUAD).unwrap();
+        let order = EulerOrder::default();
+        assert!(model.world_matrix(0, order).is_none());
+        assert!(model.mesh_matrix(0, order).is_none());
+        assert!(model.bounds(order).is_none());
+        let untransformed = model.untransformed_bounds().unwrap();
+        assert!(close(&untransformed.min, &[-0.25, -0.25, -0.75]));
+
+        // a child 2 along x under the fixture's root, which is 1 up and turned around y
+        let mut child = model.nodes[0].clone();
+        child.transform.coords.pos = [2.0, 0.0, 0.0];
+        child.transform.coords.rot = [0.0; 3];
+        model.nodes.push(child);
+        let mut hierarchy = Hierarchy::flat(&model);
+        hierarchy.node_parents[1] = Some(0);
+        hierarchy.mesh_nodes[0] = Some(1);
+        model.set_hierarchy(Some(hierarchy)).unwrap();
+
+        let root = model.world_matrix(0, order).unwrap();
+        assert!(close(&root[3], &[0.0, 1.0, 0.0, 1.0]));
+        let child = model.world_matrix(1, order).unwrap();
+        // x turned a quarter around y points down -z
+        assert!(close(&child[3], &[0.0, 1.0, -2.0, 1.0]));
+        assert_eq!(model.mesh_matrix(0, order), Some(child));
+        assert_eq!(model.mesh_matrix(1, order), Some(math::IDENTITY));
+
+        let bounds = model.bounds(order).unwrap();
+        let moved = model.meshes[0].geometry.bound().to_aabb().transform(&child);
+        assert_eq!(
+            bounds,
+            moved.union(&model.meshes[1].geometry.bound().to_aabb())
+        );
+    }
+}
@@ -0,0 +1,490 @@
+use std::borrow::Cow;
+
+use crate::anim::{Clip, Transform};
+use crate::hg::{GeometryBlock, GeometryBlockInner, PTEnum, TRS3d, VertexFeatures};
+use crate::le;
+use crate::math::{self, EulerOrder, Mat4, Vec3};
+
+// Game data is D3D: left handed, Y up, clockwise front faces, V going down
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Handedness {
+    Left,
+    Right,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UpAxis {
+    Y,
+    Z,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Winding {
+    Clockwise,
+    CounterClockwise,
+}
+
+/// Coordinate system an exporter writes in (or an importer reads from).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Conversion {
+    pub handedness: Handedness,
+    pub up: UpAxis,
+    // game units to target units
+    pub scale: f32,
+    pub flip_v: bool,
+    pub front_face: Winding,
+}
+
+impl Default for Conversion {
+    fn default() -> Self {
+        Self::d3d()
+    }
+}
+
+impl Conversion {
+    /// Leaves everything as the game has it.
+    pub fn d3d() -> Self {
+        Self {
+            handedness: Handedness::Left,
+            up: UpAxis::Y,
+            scale: 1.0,
+            flip_v: false,
+            front_face: Winding::Clockwise,
+        }
+    }
+
+    // glTF keeps V going down
+    pub fn gltf() -> Self {
+        Self {
+            handedness: Handedness::Right,
+            up: UpAxis::Y,
+            scale: 1.0,
+            flip_v: false,
+            front_face: Winding::CounterClockwise,
+        }
+    }
+
+    pub fn obj() -> Self {
+        Self {
+            flip_v: true,
+            ..Self::gltf()
+        }
+    }
+
+    pub fn blender() -> Self {
+        Self {
+            up: UpAxis::Z,
+            ..Self::obj()
+        }
+    }
+
+    /// Basis change as a row vector matrix, scale included.
+    pub fn matrix(&self) -> Mat4 {
+        let mut ret = math::IDENTITY;
+        if self.handedness == Handedness::Right {
+            ret[2][2] = -1.0;
+        }
+        if self.up == UpAxis::Z {
+            // (x, y, z) -> (x, -z, y), a quarter turn around X
+            let up = [
+                [1.0, 0.0, 0.0, 0.0],
+                [0.0, 0.0, 1.0, 0.0],
+                [0.0, -1.0, 0.0, 0.0],
+                [0.0, 0.0, 0.0, 1.0],
+            ];
+            ret = math::mul(&ret, &up);
+        }
+        let mut scale = math::scale(&math::IDENTITY, self.scale);
+        scale[3][3] = 1.0;
+        math::mul(&ret, &scale)
+    }
+
+    fn inverse_matrix(&self) -> Mat4 {
+        math::inverse(&self.matrix()).unwrap_or(math::IDENTITY)
+    }
+
+    /// Whether triangle indices have to be reversed. Moving the camera along with everything else
+    /// keeps triangles looking the same on screen, so it's down to the front face alone.
+    pub fn flips_winding(&self) -> bool {
+        self.front_face != Winding::Clockwise
+    }
+
+    pub fn point(&self, p: Vec3) -> Vec3 {
+        math::transform_point(&self.matrix(), p)
+    }
+
+    pub fn point_to_game(&self, p: Vec3) -> Vec3 {
+        math::transform_point(&self.inverse_matrix(), p)
+    }
+
+    // Normals, tangents and binormals, unit length in and out
+    pub fn vector(&self, v: Vec3) -> Vec3 {
+        normalize3(math::transform_vector(&self.matrix(), v))
+    }
+
+    pub fn vector_to_game(&self, v: Vec3) -> Vec3 {
+        normalize3(math::transform_vector(&self.inverse_matrix(), v))
+    }
+
+    // Flipping is its own inverse
+    pub fn uv(&self, [u, v]: [f32; 2]) -> [f32; 2] {
+        if self.flip_v {
+            [u, 1.0 - v]
+        } else {
+            [u, v]
+        }
+    }
+
+    pub fn triangle(&self, [a, b, c]: [u16; 3]) -> [u16; 3] {
+        if self.flips_winding() {
+            [a, c, b]
+        } else {
+            [a, b, c]
+        }
+    }
+
+    /// Same primitive type with the winding fixed up.
+    pub fn group(&self, group: &GeometryBlockInner) -> GeometryBlockInner {
+        let mut words = group.words.clone();
+        if self.flips_winding() {
+            match group.typ {
+                PTEnum::TriangleList => {
+                    for x in words.chunks_exact_mut(3) {
+                        x.swap(1, 2);
+                    }
+                }
+                // a leading degenerate moves every triangle to the other parity
+                PTEnum::TriangleStrip => {
+                    if let Some(&first) = words.first() {
+                        words.insert(0, first);
+                    }
+                }
+                PTEnum::TriangleFan if words.len() > 1 => words[1..].reverse(),
+                // anything that isn't triangles doesn't have a front
+                _ => {}
+            }
+        }
+        GeometryBlockInner {
+            typ: group.typ,
+            words,
+        }
+    }
+
+    /// Any transform from one space into another, local or model space alike.
+    pub fn transform_matrix(&self, m: &Mat4) -> Mat4 {
+        math::mul(&math::mul(&self.inverse_matrix(), m), &self.matrix())
+    }
+
+    pub fn transform(&self, t: &Transform) -> Transform {
+        let (translation, rotation, scale) =
+            math::decompose(&self.transform_matrix(&t.to_matrix()));
+        Transform {
+            translation,
+            rotation,
+            scale,
+        }
+    }
+
+    pub fn trs(&self, trs: &TRS3d, order: EulerOrder) -> TRS3d {
+        TRS3d::from_matrix(&self.transform_matrix(&trs.to_matrix(order)), order)
+    }
+
+    pub fn clip(&self, clip: &Clip) -> Clip {
+        let mut ret = clip.clone();
+        for key in ret.tracks.iter_mut().flat_map(|x| x.keys.iter_mut()) {
+            key.transform = self.transform(&key.transform);
+        }
+        ret
+    }
+
+    /// Rewrites positions, directions and UVs in the vertex data and fixes up the winding.
+    pub fn geometry<'a>(&self, geometry: &GeometryBlock<'a>) -> GeometryBlock<'a> {
+        let mut ret = geometry.clone();
+        ret.idk = geometry.idk.iter().map(|x| self.group(x)).collect();
+        ret.coords = {
+            let [x, y, z, r] = geometry.coords;
+            let [x, y, z] = self.point([x, y, z]);
+            [x, y, z, r * self.scale.abs()]
+        };
+        let Some(data) = geometry.vertex_data.as_deref() else {
+            return ret;
+        };
+        let mask = geometry.vertex_bitmask;
+        // a stale `vertex_size` means the layout can't be trusted, the vertices are left alone
+        let Some(stride) = geometry.stride().filter(|&x| x > 0) else {
+            return ret;
+        };
+        let offset = |x| mask.offset_of(x);
+        let points = offset(VertexFeatures::Position);
+        let vectors = [
+            VertexFeatures::Normal,
+            VertexFeatures::Tangent,
+            VertexFeatures::Binormal,
+        ]
+        .map(offset);
+        let uvs = [
+            VertexFeatures::TexCoordinate0,
+            VertexFeatures::TexCoordinate1,
+            VertexFeatures::TexCoordinate2,
+            VertexFeatures::TexCoordinate3,
+            VertexFeatures::TexCoordinate4,
+            VertexFeatures::TexCoordinate5,
+            VertexFeatures::TexCoordinate6,
+            VertexFeatures::TexCoordinate7,
+        ]
+        .map(offset);
+
+        let mut data = data.to_vec();
+        for vertex in data.chunks_exact_mut(stride) {
+            if let Some(at) = points {
+                let p = self.point(read(vertex, at));
+                write(vertex, at, &p);
+            }
+            for at in vectors.into_iter().flatten() {
+                let v = self.vector(read(vertex, at));
+                write(vertex, at, &v);
+            }
+            for at in uvs.into_iter().flatten() {
+                let uv = self.uv(read(vertex, at));
+                write(vertex, at, &uv);
+            }
+        }
+        ret.vertex_data = Some(Cow::Owned(data));
+        ret
+    }
+}
+
+fn normalize3([x, y, z]: Vec3) -> Vec3 {
+    let len = (x * x + y * y + z * z).sqrt();
+    if len < f32::EPSILON {
+        return [x, y, z];
+    }
+    [x / len, y / len, z / len]
+}
+
+fn read<const N: usize>(vertex: &[u8], at: usize) -> [f32; N] {
+    le::read(vertex, at)
+}
+
+fn write<const N: usize>(vertex: &mut [u8], at: usize, values: &[f32; N]) {
+    le::write(vertex, at, *values);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::anim::{Keyframe, Track};
+    use crate::model::Model;
+
+    const QUAD: &[u8] = include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/quad.hgm"));
+
+    const PRESETS: [fn() -> Conversion; 4] = [
+        Conversion::d3d,
+        Conversion::gltf,
+        Conversion::obj,
+        Conversion::blender,
+    ];
+
+    fn close(a: &[f32], b: &[f32]) -> bool {
+        a.len() == b.len() && a.iter().zip(b).all(|(x, y)| (x - y).abs() < 1e-4)
+    }
+
+    fn sub([a, b, c]: Vec3, [x, y, z]: Vec3) -> Vec3 {
+        [a - x, b - y, c - z]
+    }
+
+    fn cross([a, b, c]: Vec3, [x, y, z]: Vec3) -> Vec3 {
+        [b * z - c * y, c * x - a * z, a * y - b * x]
+    }
+
+    fn dot(a: Vec3, b: Vec3) -> f32 {
+        a.iter().zip(&b).map(|(x, y)| x * y).sum()
+    }
+
+    // Normal of the front face, the cross product points out of it for clockwise fronts in a
+    // left handed space and for counter clockwise ones in a right handed space
+    fn front_normal(conversion: &Conversion, p: &[Vec3], [a, b, c]: [u16; 3]) -> Vec3 {
+        let [a, b, c] = [a, b, c].map(|x| p[x as usize]);
+        let n = cross(sub(b, a), sub(c, a));
+        let same = (conversion.handedness == Handedness::Left)
+            == (conversion.front_face == Winding::Clockwise);
+        if same {
+            n
+        } else {
+            n.map(|x| -x)
+        }
+    }
+
+    #[test]
+    fn presets_move_points() {
+        let p = [1.0, 2.0, 3.0];
+        let expected = [
+            [1.0, 2.0, 3.0],
+            [1.0, 2.0, -3.0],
+            [1.0, 2.0, -3.0],
+            // forward is +y and up is +z
+            [1.0, 3.0, 2.0],
+        ];
+        for (preset, expected) in PRESETS.iter().zip(expected) {
+            let conversion = preset();
+            let moved = math::transform_point(&conversion.matrix(), p);
+            assert!(close(&moved, &expected), "{conversion:?}: {moved:?}");
+            assert!(close(&conversion.point(p), &expected));
+            assert!(close(&conversion.point_to_game(moved), &p));
+        }
+        let scaled = Conversion {
+            scale: 2.0,
+            ..Conversion::blender()
+        };
+        assert!(close(&scaled.point(p), &[2.0, 6.0, 4.0]));
+        // directions don't scale
+        assert!(close(&scaled.vector([0.0, 0.0, 2.0]), &[0.0, 1.0, 0.0]));
+    }
+
+    #[test]
+    fn winding_flips_with_handedness() {
+        for preset in PRESETS {
+            let conversion = preset();
+            assert_eq!(
+                conversion.flips_winding(),
+                conversion.handedness == Handedness::Right,
+                "{conversion:?}"
+            );
+        }
+        assert_eq!(Conversion::obj().triangle([0, 1, 2]), [0, 2, 1]);
+        assert_eq!(Conversion::d3d().triangle([0, 1, 2]), [0, 1, 2]);
+    }
+
+    #[test]
+    fn groups_keep_their_triangles() {
+        let groups = [
+            (PTEnum::TriangleList, vec![0, 1, 2, 2, 1, 3]),
+            (PTEnum::TriangleStrip, vec![0, 1, 2, 3, 4]),
+            (PTEnum::TriangleFan, vec![0, 1, 2, 3, 4]),
+        ];
+        for (typ, words) in groups {
+            let group = GeometryBlockInner { typ, words };
+            let mut reversed: Vec<_> = group
+                .triangles()
+                .iter()
+                .map(|&[a, b, c]| [a, c, b])
+                .collect();
+            let mut flipped = Conversion::gltf().group(&group).triangles();
+            assert_eq!(Conversion::gltf().group(&group).typ, typ);
+            // same triangles, started from wherever
+            for t in reversed.iter_mut().chain(&mut flipped) {
+                let min = (0..3).min_by_key(|&i| t[i]).unwrap();
+                t.rotate_left(min);
+            }
+            reversed.sort_unstable();
+            flipped.sort_unstable();
+            assert_eq!(flipped, reversed, "{typ:?}");
+            assert_eq!(Conversion::d3d().group(&group).words, group.words);
+        }
+    }
+
+    #[test]
+    fn converted_quad_faces_its_normals() {
+        let model = Model::read(QUAD).unwrap();
+        let geometry = &model.meshes[0].geometry;
+        let stride = geometry.stride().unwrap();
+        let normal_at = geometry
+            .vertex_bitmask
+            .offset_of(VertexFeatures::Normal)
+            .unwrap();
+        for preset in PRESETS {
+            let conversion = preset();
+            let converted = conversion.geometry(geometry);
+            let p = converted.positions().unwrap();
+            let data = converted.vertex_data.as_deref().unwrap();
+            let triangles = converted.idk[0].triangles();
+            assert_eq!(triangles.len(), 2);
+            for t in triangles {
+                let normal: Vec3 = read(&data[t[0] as usize * stride..], normal_at);
+                let n = front_normal(&conversion, &p, t);
+                assert!(dot(n, normal) > 0.0, "{conversion:?} {t:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn flips_v() {
+        let model = Model::read(QUAD).unwrap();
+        let geometry = &model.meshes[0].geometry;
+        let stride = geometry.stride().unwrap();
+        let at = geometry
+            .vertex_bitmask
+            .offset_of(VertexFeatures::TexCoordinate0)
+            .unwrap();
+        let uvs = |g: &GeometryBlock| -> Vec<[f32; 2]> {
+            g.vertex_data
+                .as_deref()
+                .unwrap()
+                .chunks_exact(stride)
+                .map(|x| read(x, at))
+                .collect()
+        };
+        let before = uvs(geometry);
+        let flipped: Vec<_> = before.iter().map(|&[u, v]| [u, 1.0 - v]).collect();
+        assert_eq!(uvs(&Conversion::obj().geometry(geometry)), flipped);
+        assert_eq!(uvs(&Conversion::gltf().geometry(geometry)), before);
+        assert_eq!(Conversion::obj().uv([0.25, 0.75]), [0.25, 0.25]);
+    }
+
+    #[test]
+    fn transforms_match_baked_meshes() {
+        let model = Model::read(QUAD).unwrap();
+        let geometry = &model.meshes[0].geometry;
+        let trs = TRS3d {
+            pos: [1.0, -2.0, 3.0],
+            rot: [0.3, -0.7, 1.1],
+            scale: [1.0, 2.0, 3.0],
+        };
+        let order = EulerOrder::default();
+        let m = trs.to_matrix(order);
+        let transform = trs.to_transform(order);
+        let clip = Clip {
+            name: "still".to_string(),
+            duration: 1.0,
+            tracks: vec![Track {
+                target: "node".to_string(),
+                keys: vec![Keyframe {
+                    time: 0.0,
+                    transform,
+                }],
+            }],
+        };
+        for preset in PRESETS {
+            let conversion = Conversion {
+                scale: 0.5,
+                ..preset()
+            };
+            // baked in the game's space, then converted
+            let baked: Vec<Vec3> = geometry
+                .positions()
+                .unwrap()
+                .into_iter()
+                .map(|p| conversion.point(math::transform_point(&m, p)))
+                .collect();
+            let local = conversion.geometry(geometry).positions().unwrap();
+            let matrices = [
+                conversion.transform_matrix(&m),
+                conversion.trs(&trs, order).to_matrix(order),
+                conversion.transform(&transform).to_matrix(),
+                conversion.clip(&clip).tracks[0].keys[0]
+                    .transform
+                    .to_matrix(),
+            ];
+            for matrix in matrices {
+                for (p, expected) in local.iter().zip(&baked) {
+                    let world = math::transform_point(&matrix, *p);
+                    assert!(
+                        close(&world, expected),
+                        "{conversion:?}: {world:?} {expected:?}"
+                    );
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,184 @@
+use std::borrow::Cow;
+
+use serde::de::Error;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use super::VertexMask;
+use crate::name::{Name, NameEncoding};
+
+impl Serialize for VertexMask {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.names().serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for VertexMask {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let names = Vec::<String>::deserialize(deserializer)?;
+        VertexMask::from_names(&names).map_err(D::Error::custom)
+    }
+}
+
+// UTF-8 names are plain strings, anything else keeps its raw bytes so it writes back the same
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum NameRepr<'a> {
+    Text(Cow<'a, str>),
+    Raw {
+        text: Cow<'a, str>,
+        #[serde(with = "bytes")]
+        raw: Cow<'a, [u8]>,
+    },
+}
+
+impl Serialize for Name<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.encoding() {
+            NameEncoding::Utf8 => NameRepr::Text(Cow::Borrowed(self.as_str())),
+            _ => NameRepr::Raw {
+                text: Cow::Borrowed(self.as_str()),
+                raw: Cow::Borrowed(self.raw()),
+            },
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Name<'_> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(match NameRepr::deserialize(deserializer)? {
+            NameRepr::Text(text) => Name::decode_owned(text.into_owned().into_bytes()),
+            // the text is only there for people reading the JSON
+            NameRepr::Raw { raw, .. } => Name::decode_owned(raw.into_owned()),
+        })
+    }
+}
+
+// Byte blobs as base64 strings
+pub mod bytes {
+    use std::borrow::Cow;
+
+    use base64::Engine;
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(value: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&base64::engine::general_purpose::STANDARD.encode(value))
+    }
+
+    pub fn deserialize<'de, 'a, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Cow<'a, [u8]>, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        base64::engine::general_purpose::STANDARD
+            .decode(value)
+            .map(Cow::Owned)
+            .map_err(D::Error::custom)
+    }
+}
+
+pub mod opt_bytes {
+    use std::borrow::Cow;
+
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    // Formats like RON tell `Some(x)` from `x`, so the bytes have to go through serialize_some
+    #[derive(Serialize, Deserialize)]
+    struct Wrapper<'a>(#[serde(with = "super::bytes")] Cow<'a, [u8]>);
+
+    pub fn serialize<S: Serializer>(
+        value: &Option<Cow<'_, [u8]>>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match value {
+            Some(x) => serializer.serialize_some(&Wrapper(Cow::Borrowed(x))),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, 'a, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Cow<'a, [u8]>>, D::Error> {
+        Ok(Option::<Wrapper>::deserialize(deserializer)?.map(|x| x.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{GeometryBlock, OwnedBlock};
+    use super::*;
+    use crate::hg::{Block, VertexFeatures};
+
+    fn geometry(vertex_data: Option<Vec<u8>>) -> OwnedBlock {
+        let mut mask = VertexMask::none();
+        mask.set(VertexFeatures::Position);
+        Block::Geometry(GeometryBlock {
+            name: Name::new("\u{7bb1}"),
+            coords: [0.0, 1.0, 2.0, 3.5],
+            bool4: true,
+            vertex_bitmask: mask,
+            bool6: vertex_data.is_none(),
+            idk: Vec::new(),
+            vertex_num: vertex_data.as_ref().map(|x| (x.len() / 12) as u32),
+            vertex_size: vertex_data.as_ref().map(|_| 12),
+            vertex_data: vertex_data.map(Cow::Owned),
+            rest: Cow::Owned(vec![1, 2, 3]),
+        })
+    }
+
+    fn vertex_data(block: &OwnedBlock) -> Option<&[u8]> {
+        match block {
+            Block::Geometry(x) => x.vertex_data.as_deref(),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn optional_bytes_roundtrip() {
+        for data in [None, Some(vec![0x55; 24]), Some(Vec::new())] {
+            let block = geometry(data.clone());
+
+            let json = serde_json::to_string(&block).unwrap();
+            let back: OwnedBlock = serde_json::from_str(&json).unwrap();
+            assert_eq!(vertex_data(&back), data.as_deref(), "{json}");
+            assert_eq!(format!("{back:?}"), format!("{block:?}"));
+
+            let ron = ron::to_string(&block).unwrap();
+            let back: OwnedBlock = ron::from_str(&ron).unwrap();
+            assert_eq!(vertex_data(&back), data.as_deref(), "{ron}");
+            assert_eq!(format!("{back:?}"), format!("{block:?}"));
+        }
+    }
+
+    const QUAD: &[u8] = include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/quad.hgm"));
+
+    fn through_json(blocks: &[Block]) -> Vec<u8> {
+        let json = serde_json::to_string(blocks).unwrap();
+        let back: Vec<OwnedBlock> = serde_json::from_str(&json).unwrap();
+        crate::hg::write_blocks(&back).unwrap()
+    }
+
+    #[test]
+    fn file_roundtrips_through_json() {
+        let blocks = crate::hg::read_blocks(QUAD).unwrap();
+        assert_eq!(through_json(&blocks), QUAD);
+
+        // names that only survive as raw bytes: Shift-JIS and bytes that aren't anything
+        let mut blocks: Vec<OwnedBlock> = blocks.into_iter().map(Block::into_owned).collect();
+        let names = [vec![0x83, 0x65, 0x83, 0x58, 0x83, 0x67], vec![b'a', 0x83]];
+        for (block, name) in blocks.iter_mut().zip(names) {
+            match block {
+                Block::Transform(x) => x.name = Name::decode_owned(name),
+                Block::Bone(x) => x.name = Name::decode_owned(name),
+                x => panic!("unexpected {x:?}"),
+            }
+        }
+        let expected = crate::hg::write_blocks(&blocks).unwrap();
+        assert_eq!(through_json(&blocks), expected);
+        let json = serde_json::to_string(&blocks).unwrap();
+        assert!(
+            json.contains(r#"{"text":"テスト","raw":"g2WDWINn"}"#),
+            "{json}"
+        );
+    }
+}
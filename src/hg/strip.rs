@@ -0,0 +1,286 @@
+use std::collections::{HashMap, HashSet};
+
+use super::{GeometryBlockInner, PTEnum};
+
+impl GeometryBlockInner {
+    /// Triangles in D3D winding (clockwise), degenerates dropped.
+    pub fn triangles(&self) -> Vec<[u16; 3]> {
+        let w = &self.words;
+        let ret: Vec<[u16; 3]> = match self.typ {
+            PTEnum::TriangleList => w.chunks_exact(3).map(|x| [x[0], x[1], x[2]]).collect(),
+            // every other triangle in a strip is flipped
+            PTEnum::TriangleStrip => (0..w.len().saturating_sub(2))
+                .map(|i| {
+                    if i & 1 == 0 {
+                        [w[i], w[i + 1], w[i + 2]]
+                    } else {
+                        [w[i + 1], w[i], w[i + 2]]
+                    }
+                })
+                .collect(),
+            PTEnum::TriangleFan => (1..w.len().saturating_sub(1))
+                .map(|i| [w[0], w[i], w[i + 1]])
+                .collect(),
+            _ => Vec::new(),
+        };
+        ret.into_iter()
+            .filter(|[a, b, c]| a != b && b != c && a != c)
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StripStats {
+    pub triangles: usize,
+    pub strips: usize,
+    // as a triangle list
+    pub list_indices: usize,
+    // after stitching, degenerates included
+    pub strip_indices: usize,
+}
+
+impl StripStats {
+    pub fn saved(&self) -> isize {
+        self.list_indices as isize - self.strip_indices as isize
+    }
+}
+
+impl std::ops::AddAssign for StripStats {
+    fn add_assign(&mut self, rhs: Self) {
+        self.triangles += rhs.triangles;
+        self.strips += rhs.strips;
+        self.list_indices += rhs.list_indices;
+        self.strip_indices += rhs.strip_indices;
+    }
+}
+
+impl std::fmt::Display for StripStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} triangles in {} strips, {} indices instead of {} ({} saved)",
+            self.triangles,
+            self.strips,
+            self.strip_indices,
+            self.list_indices,
+            self.saved()
+        )
+    }
+}
+
+// Directed edge -> triangles that have it, with the vertex opposite to it
+type Edges = HashMap<(u16, u16), Vec<(usize, u16)>>;
+
+// Follows shared edges from `start` as long as the winding works out
+fn grow(
+    triangles: &[[u16; 3]],
+    edges: &Edges,
+    used: &[bool],
+    start: usize,
+    rotation: usize,
+) -> Vec<u16> {
+    let [a, b, c] = triangles[start];
+    let mut strip = match rotation {
+        0 => vec![a, b, c],
+        1 => vec![b, c, a],
+        _ => vec![c, a, b],
+    };
+    let mut taken = HashSet::from([start]);
+    loop {
+        let n = strip.len();
+        let (x, y) = (strip[n - 2], strip[n - 1]);
+        // the next triangle is (x, y, d) on even positions and (y, x, d) on odd ones
+        let edge = if (n - 2) & 1 == 0 { (x, y) } else { (y, x) };
+        let next = edges
+            .get(&edge)
+            .and_then(|x| x.iter().find(|(t, _)| !used[*t] && !taken.contains(t)));
+        match next {
+            Some(&(t, d)) => {
+                taken.insert(t);
+                strip.push(d);
+            }
+            None => break,
+        }
+    }
+    strip
+}
+
+/// Glues strips together with degenerate triangles, keeping each one's winding.
+pub fn join_strips<I: IntoIterator<Item = Vec<u16>>>(strips: I) -> Vec<u16> {
+    let mut ret = Vec::new();
+    for strip in strips {
+        let Some(&first) = strip.first() else {
+            continue;
+        };
+        if let Some(&last) = ret.last() {
+            ret.extend_from_slice(&[last, first, first]);
+            // strips have to start on an even position
+            if ret.len().is_multiple_of(2) {
+                ret.push(first);
+            }
+            ret.extend_from_slice(&strip[1..]);
+        } else {
+            ret.extend_from_slice(&strip);
+        }
+    }
+    ret
+}
+
+/// Rebuilds a triangle group as a single stitched strip.
+pub fn stripify(group: &GeometryBlockInner) -> (GeometryBlockInner, StripStats) {
+    // anything that isn't triangles is left alone
+    if !group.typ.is_triangles() {
+        return (group.clone(), StripStats::default());
+    }
+    let triangles = group.triangles();
+    let mut edges = Edges::new();
+    for (i, &[a, b, c]) in triangles.iter().enumerate() {
+        edges.entry((a, b)).or_default().push((i, c));
+        edges.entry((b, c)).or_default().push((i, a));
+        edges.entry((c, a)).or_default().push((i, b));
+    }
+
+    let mut used = vec![false; triangles.len()];
+    let mut strips = Vec::new();
+    for start in 0..triangles.len() {
+        if used[start] {
+            continue;
+        }
+        let strip = (0..3)
+            .map(|rotation| grow(&triangles, &edges, &used, start, rotation))
+            .max_by_key(Vec::len)
+            .unwrap_or_default();
+        // replay it to mark what got taken, grow doesn't touch `used`
+        for (parity, w) in strip.windows(3).enumerate() {
+            let t = if parity & 1 == 0 {
+                [w[0], w[1], w[2]]
+            } else {
+                [w[1], w[0], w[2]]
+            };
+            let found = edges
+                .get(&(t[0], t[1]))
+                .and_then(|x| x.iter().find(|(i, d)| !used[*i] && *d == t[2]));
+            if let Some(&(i, _)) = found {
+                used[i] = true;
+            }
+        }
+        strips.push(strip);
+    }
+
+    let stats_strips = strips.len();
+    let words = join_strips(strips);
+    let stats = StripStats {
+        triangles: triangles.len(),
+        strips: stats_strips,
+        list_indices: triangles.len() * 3,
+        strip_indices: words.len(),
+    };
+    (
+        GeometryBlockInner {
+            typ: PTEnum::TriangleStrip,
+            words,
+        },
+        stats,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Same triangle whichever vertex it starts from, winding kept
+    fn normalized(mut triangles: Vec<[u16; 3]>) -> Vec<[u16; 3]> {
+        for t in &mut triangles {
+            let min = (0..3).min_by_key(|&i| t[i]).unwrap_or(0);
+            t.rotate_left(min);
+        }
+        triangles.sort_unstable();
+        triangles
+    }
+
+    fn list(triangles: &[[u16; 3]]) -> GeometryBlockInner {
+        GeometryBlockInner {
+            typ: PTEnum::TriangleList,
+            words: triangles.iter().flatten().copied().collect(),
+        }
+    }
+
+    // w x h quads, both triangles clockwise
+    fn grid(w: u16, h: u16) -> Vec<[u16; 3]> {
+        let mut ret = Vec::new();
+        for y in 0..h {
+            for x in 0..w {
+                let i = y * (w + 1) + x;
+                let j = i + w + 1;
+                ret.push([i, j, i + 1]);
+                ret.push([i + 1, j, j + 1]);
+            }
+        }
+        ret
+    }
+
+    fn check(triangles: Vec<[u16; 3]>) {
+        let (strip, stats) = stripify(&list(&triangles));
+        assert_eq!(strip.typ, PTEnum::TriangleStrip);
+        // degenerates are dropped, anything else left over would be an extra triangle at a join
+        assert_eq!(normalized(strip.triangles()), normalized(triangles.clone()));
+        assert_eq!(stats.triangles, triangles.len());
+        assert_eq!(stats.strip_indices, strip.words.len());
+    }
+
+    #[test]
+    fn stripify_keeps_triangles() {
+        check(grid(1, 1));
+        check(grid(4, 3));
+        check(grid(7, 1));
+        // strips of both parities glued one after the other
+        check(vec![
+            [0, 1, 2],
+            [3, 4, 5],
+            [4, 6, 5],
+            [7, 8, 9],
+            [8, 10, 9],
+            [10, 11, 9],
+        ]);
+        // a fan and a lone triangle sharing vertices with it
+        check(vec![[0, 1, 2], [0, 2, 3], [0, 3, 4], [0, 4, 5], [5, 4, 1]]);
+
+        // holes in a bigger grid, the same every run
+        let mut seed = 0x2545_F491u32;
+        let holes: Vec<[u16; 3]> = grid(9, 9)
+            .into_iter()
+            .filter(|_| {
+                seed ^= seed << 13;
+                seed ^= seed >> 17;
+                seed ^= seed << 5;
+                !seed.is_multiple_of(4)
+            })
+            .collect();
+        check(holes);
+    }
+
+    #[test]
+    fn join_keeps_winding() {
+        let joined = join_strips([vec![0, 1, 2], vec![3, 4, 5, 6], vec![7, 8, 9]]);
+        let group = GeometryBlockInner {
+            typ: PTEnum::TriangleStrip,
+            words: joined,
+        };
+        let expected = vec![[0, 1, 2], [3, 4, 5], [5, 4, 6], [7, 8, 9]];
+        assert_eq!(normalized(group.triangles()), normalized(expected));
+    }
+
+    #[test]
+    fn expands_strips_and_fans() {
+        let strip = GeometryBlockInner {
+            typ: PTEnum::TriangleStrip,
+            words: vec![0, 1, 2, 3, 3, 4],
+        };
+        assert_eq!(strip.triangles(), [[0, 1, 2], [2, 1, 3]]);
+        let fan = GeometryBlockInner {
+            typ: PTEnum::TriangleFan,
+            words: vec![0, 1, 2, 3],
+        };
+        assert_eq!(fan.triangles(), [[0, 1, 2], [0, 2, 3]]);
+    }
+}
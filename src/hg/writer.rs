@@ -0,0 +1,189 @@
+use thiserror::Error;
+
+use super::{Block, BoneBlock, GeometryBlock, TransformBlock};
+use crate::le;
+
+#[derive(Error, Debug)]
+pub enum WriteError {
+    #[error(
+        "geometry {name:?}: {vertices} vertices of {size} bytes don't match {len} bytes of data"
+    )]
+    VertexData {
+        name: String,
+        vertices: u32,
+        size: usize,
+        len: usize,
+    },
+    #[error("geometry {name:?}: vertex size {stored} doesn't match {mask} from the vertex mask")]
+    VertexSize {
+        name: String,
+        stored: usize,
+        mask: usize,
+    },
+    #[error("block {0} is too large for its 32 bit size")]
+    TooLarge(usize),
+}
+
+fn write_u32(out: &mut Vec<u8>, value: u32) {
+    le::push(out, value);
+}
+
+fn write_f32s(out: &mut Vec<u8>, values: &[f32]) {
+    le::push_all(out, values);
+}
+
+// Null terminated and padded to 4 bytes, the inverse of read_str
+fn write_str(out: &mut Vec<u8>, value: &[u8]) {
+    let skip = 4 * (value.len() / 4) + 4;
+    out.extend_from_slice(value);
+    out.resize(out.len() + skip - value.len(), 0);
+}
+
+fn write_transform(out: &mut Vec<u8>, block: &TransformBlock) {
+    write_str(out, block.name.raw());
+    write_u32(out, block.idk);
+    write_f32s(out, &block.coords.pos);
+    write_f32s(out, &block.coords.rot);
+    write_f32s(out, &block.coords.scale);
+    out.extend_from_slice(&block.rest);
+}
+
+fn write_bone(out: &mut Vec<u8>, block: &BoneBlock) {
+    write_str(out, block.name.raw());
+    write_u32(out, block.idk);
+    le::push(out, block.coords);
+    le::push(out, crate::math::to_columns_4x3(&block.matrix));
+    out.extend_from_slice(&block.rest);
+}
+
+fn write_geometry(out: &mut Vec<u8>, block: &GeometryBlock) -> Result<(), WriteError> {
+    write_str(out, block.name.raw());
+    write_f32s(out, &block.coords);
+    write_u32(out, block.bool4 as u32);
+    write_u32(out, block.vertex_bitmask.mask);
+    write_u32(out, block.bool6 as u32);
+    if !block.bool6 {
+        let vertex_data = block.vertex_data.as_deref().unwrap_or_default();
+        let vertex_size = block.stride().ok_or_else(|| WriteError::VertexSize {
+            name: block.name.to_string(),
+            stored: block.vertex_size.unwrap_or_default(),
+            mask: block.vertex_bitmask.vertex_size(),
+        })?;
+        let vertex_num = block
+            .vertex_num
+            .unwrap_or((vertex_data.len() / vertex_size.max(1)) as u32);
+        // read_blocks would take the wrong amount of bytes as vertices and misparse the rest
+        if (vertex_num as usize).checked_mul(vertex_size) != Some(vertex_data.len()) {
+            return Err(WriteError::VertexData {
+                name: block.name.to_string(),
+                vertices: vertex_num,
+                size: vertex_size,
+                len: vertex_data.len(),
+            });
+        }
+        write_u32(out, vertex_num);
+        out.extend_from_slice(vertex_data);
+
+        write_u32(out, block.idk.len() as u32);
+        for group in &block.idk {
+            write_u32(out, (&group.typ).into());
+            write_u32(out, group.words.len() as u32);
+            le::push_all(out, &group.words);
+        }
+    }
+    out.extend_from_slice(&block.rest);
+    Ok(())
+}
+
+/// Serializes blocks back into the chunk format `read_blocks` takes.
+pub fn write_blocks(blocks: &[Block]) -> Result<Vec<u8>, WriteError> {
+    let mut ret = Vec::new();
+    for (i, block) in blocks.iter().enumerate() {
+        let start = ret.len();
+        write_u32(&mut ret, block.typ());
+        // patched once the body is out
+        write_u32(&mut ret, 0);
+        match block {
+            Block::Geometry(g) => write_geometry(&mut ret, g)?,
+            Block::Transform(t) => write_transform(&mut ret, t),
+            Block::Bone(b) => write_bone(&mut ret, b),
+            Block::Raw(raw) => ret.extend_from_slice(&raw.data),
+        }
+        let size = u32::try_from(ret.len() - start).map_err(|_| WriteError::TooLarge(i))?;
+        le::write(&mut ret, start + 4, size);
+    }
+    Ok(ret)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hg::read_blocks;
+    use crate::model::Model;
+
+    // Hand-built: transform, bone, a shader block nothing parses, a textured quad strip and a
+    // geometry block without vertices
+    const QUAD: &[u8] = include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/quad.hgm"));
+
+    #[test]
+    fn read_write_read() {
+        let blocks = read_blocks(QUAD).unwrap();
+        assert_eq!(blocks.len(), 5);
+        assert!(matches!(&blocks[2], Block::Raw(raw) if raw.typ == 1));
+        let written = write_blocks(&blocks).unwrap();
+        assert_eq!(written, QUAD);
+        let again = read_blocks(&written).unwrap();
+        assert_eq!(format!("{again:?}"), format!("{blocks:?}"));
+    }
+
+    #[test]
+    fn model_roundtrip() {
+        let model = Model::read(QUAD).unwrap();
+        assert_eq!(write_blocks(&model.into_blocks()).unwrap(), QUAD);
+    }
+
+    fn quad<'a, 'b>(blocks: &'b mut [Block<'a>]) -> &'b mut GeometryBlock<'a> {
+        match &mut blocks[3] {
+            Block::Geometry(x) => x,
+            x => panic!("not geometry: {x:?}"),
+        }
+    }
+
+    #[test]
+    fn vertex_count_must_match_data() {
+        let mut blocks = read_blocks(QUAD).unwrap();
+        quad(&mut blocks).vertex_num = Some(5);
+        assert!(matches!(
+            write_blocks(&blocks),
+            Err(WriteError::VertexData { vertices: 5, .. })
+        ));
+        let geometry = quad(&mut blocks);
+        geometry.vertex_num = None;
+        geometry.vertex_data.as_mut().unwrap().to_mut().pop();
+        assert!(write_blocks(&blocks).is_err());
+    }
+
+    #[test]
+    fn vertex_size_must_match_mask() {
+        let mut blocks = read_blocks(QUAD).unwrap();
+        let geometry = quad(&mut blocks);
+        geometry
+            .vertex_bitmask
+            .unset(crate::hg::VertexFeatures::TexCoordinate0);
+        assert_eq!(geometry.stride(), None);
+        assert!(matches!(
+            write_blocks(&blocks),
+            Err(WriteError::VertexSize {
+                stored: 32,
+                mask: 24,
+                ..
+            })
+        ));
+        // with the size gone the mask is used, and 4 vertices of 24 bytes aren't what's there
+        quad(&mut blocks).vertex_size = None;
+        assert!(matches!(
+            write_blocks(&blocks),
+            Err(WriteError::VertexData { size: 24, .. })
+        ));
+    }
+}
@@ -0,0 +1,638 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+use thiserror::Error;
+
+use crate::bounds::Sphere;
+use crate::convert::Conversion;
+use crate::hg::strip::stripify;
+use crate::hg::{GeometryBlock, GeometryBlockInner, PTEnum, VertexFeatures, VertexMask};
+use crate::le;
+use crate::name::Name;
+
+// u16 indices, so that's how many vertices fit in one geometry block
+pub const MAX_VERTICES: usize = u16::MAX as usize;
+
+#[derive(Error, Debug)]
+pub enum ImportError {
+    #[error("line {0}: {1}")]
+    Obj(usize, String),
+    #[error("unsupported primitive mode {0}")]
+    UnsupportedMode(String),
+    #[error("missing `{0}`")]
+    Missing(&'static str),
+    #[error("{name} has {len} values for {vertices} vertices")]
+    AttributeCount {
+        name: &'static str,
+        len: usize,
+        vertices: usize,
+    },
+    #[error("index {0} out of range for {1} vertices")]
+    BadIndex(u32, usize),
+    #[error("{0} indices don't make whole triangles")]
+    IndexCount(usize),
+    #[cfg(feature = "gltf")]
+    #[error(transparent)]
+    Gltf(#[from] gltf::Error),
+    #[cfg(feature = "gltf")]
+    #[error(transparent)]
+    Base64(#[from] base64::DecodeError),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+/// Triangle list mesh with per vertex attributes, empty attributes are absent.
+#[derive(Debug, Clone, Default)]
+pub struct Mesh {
+    pub name: String,
+    pub positions: Vec<[f32; 3]>,
+    pub normals: Vec<[f32; 3]>,
+    pub colors: Vec<[f32; 4]>,
+    pub weights: Vec<[f32; 4]>,
+    // Written as 4 floats, which is a guess: no file has been seen to confirm how the game
+    // stores them (u32s or bytes would fit the same 16 bytes)
+    pub joints: Vec<[u32; 4]>,
+    // one per set, up to 8
+    pub tex_coords: Vec<Vec<[f32; 2]>>,
+    // front faces wind the way `ImportOptions::source` says
+    pub indices: Vec<u32>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ImportOptions {
+    pub strip: bool,
+    // what the file uses, converted back to what the game uses
+    pub source: Conversion,
+}
+
+impl Default for ImportOptions {
+    fn default() -> Self {
+        Self {
+            strip: false,
+            source: Conversion::obj(),
+        }
+    }
+}
+
+impl Mesh {
+    pub fn vertex_mask(&self) -> VertexMask {
+        let mut mask = VertexMask::none();
+        mask.set(VertexFeatures::Position);
+        if !self.normals.is_empty() {
+            mask.set(VertexFeatures::Normal);
+        }
+        if !self.colors.is_empty() {
+            mask.set(VertexFeatures::Color0);
+        }
+        if !self.weights.is_empty() {
+            mask.set(VertexFeatures::Weight0);
+            mask.set(VertexFeatures::Weight1);
+            mask.set(VertexFeatures::Weight2);
+            mask.set(VertexFeatures::Weight3);
+            mask.set(VertexFeatures::WeightIndicies);
+        }
+        for (i, _) in self.tex_coords.iter().enumerate().take(8) {
+            mask.set(VertexMask::from_bits(
+                (VertexFeatures::TexCoordinate0 as u32) << i,
+            ));
+        }
+        mask
+    }
+
+    /// Every attribute has one value per position and every index points at one.
+    pub fn validate(&self) -> Result<(), ImportError> {
+        let vertices = self.positions.len();
+        let counts = [
+            ("normals", self.normals.len()),
+            ("colors", self.colors.len()),
+            ("weights", self.weights.len()),
+            ("joints", self.joints.len()),
+        ];
+        let tex_coords = self
+            .tex_coords
+            .iter()
+            .take(8)
+            .map(|x| ("tex_coords", x.len()));
+        for (name, len) in counts.into_iter().chain(tex_coords) {
+            if len != 0 && len != vertices {
+                return Err(ImportError::AttributeCount {
+                    name,
+                    len,
+                    vertices,
+                });
+            }
+        }
+        // weights without joints can't be written
+        if !self.weights.is_empty() && self.joints.len() != vertices {
+            return Err(ImportError::AttributeCount {
+                name: "joints",
+                len: self.joints.len(),
+                vertices,
+            });
+        }
+        if !self.indices.len().is_multiple_of(3) {
+            return Err(ImportError::IndexCount(self.indices.len()));
+        }
+        match self.indices.iter().find(|&&x| x as usize >= vertices) {
+            Some(&x) => Err(ImportError::BadIndex(x, vertices)),
+            None => Ok(()),
+        }
+    }
+
+    // Attributes in bit order, like read_blocks expects them. Only call after `validate`
+    fn write_vertex(&self, i: usize, source: &Conversion, out: &mut Vec<u8>) {
+        let mut put = |values: &[f32]| le::push_all(out, values);
+        put(&source.point_to_game(self.positions[i]));
+        if !self.normals.is_empty() {
+            put(&source.vector_to_game(self.normals[i]));
+        }
+        if !self.colors.is_empty() {
+            put(&self.colors[i]);
+        }
+        if !self.weights.is_empty() {
+            put(&self.weights[i]);
+            // unverified: indices are assumed to be floats like everything else
+            put(&self.joints[i].map(|x| x as f32));
+        }
+        for set in self.tex_coords.iter().take(8) {
+            put(&source.uv(set[i]));
+        }
+    }
+
+    /// Splits the mesh into geometry blocks with at most `MAX_VERTICES` vertices each.
+    pub fn to_geometry(
+        &self,
+        options: &ImportOptions,
+    ) -> Result<Vec<GeometryBlock<'static>>, ImportError> {
+        self.validate()?;
+        let mask = self.vertex_mask();
+        let vertex_size = mask.vertex_size();
+
+        let mut ret = Vec::new();
+        let mut remap = HashMap::new();
+        let mut vertices = Vec::new();
+        let mut triangles: Vec<[u16; 3]> = Vec::new();
+        let mut flush = |vertices: &mut Vec<usize>, triangles: &mut Vec<[u16; 3]>| {
+            if triangles.is_empty() {
+                return;
+            }
+            let mut vertex_data = Vec::with_capacity(vertices.len() * vertex_size);
+            for &v in vertices.iter() {
+                self.write_vertex(v, &options.source, &mut vertex_data);
+            }
+            let positions: Vec<[f32; 3]> = vertices
+                .iter()
+                .map(|&v| options.source.point_to_game(self.positions[v]))
+                .collect();
+            let name = match ret.len() {
+                0 => self.name.clone(),
+                i => format!("{}_{i}", self.name),
+            };
+            let mut group = GeometryBlockInner {
+                typ: PTEnum::TriangleList,
+                words: triangles.iter().flatten().copied().collect(),
+            };
+            if options.strip {
+                group = stripify(&group).0;
+            }
+            ret.push(GeometryBlock {
+                name: Name::new(name),
+                coords: Sphere::from_points(&positions)
+                    .map(|x| x.to_coords())
+                    .unwrap_or_default(),
+                bool4: false,
+                vertex_bitmask: mask,
+                bool6: false,
+                idk: vec![group],
+                vertex_num: Some(vertices.len() as u32),
+                vertex_size: Some(vertex_size),
+                vertex_data: Some(Cow::Owned(vertex_data)),
+                rest: Cow::Owned(Vec::new()),
+            });
+            vertices.clear();
+            triangles.clear();
+        };
+
+        for triangle in self.indices.chunks_exact(3) {
+            let new = triangle.iter().filter(|x| !remap.contains_key(*x)).count();
+            if vertices.len() + new > MAX_VERTICES {
+                flush(&mut vertices, &mut triangles);
+                remap.clear();
+            }
+            let mut local = [0u16; 3];
+            for (l, &v) in local.iter_mut().zip(triangle) {
+                *l = *remap.entry(v).or_insert_with(|| {
+                    vertices.push(v as usize);
+                    (vertices.len() - 1) as u16
+                });
+            }
+            triangles.push(options.source.triangle(local));
+        }
+        flush(&mut vertices, &mut triangles);
+        Ok(ret)
+    }
+}
+
+fn parse_floats<const N: usize>(line: usize, parts: &[&str]) -> Result<[f32; N], ImportError> {
+    let mut ret = [0f32; N];
+    for (i, x) in ret.iter_mut().enumerate() {
+        let part = parts
+            .get(i)
+            .ok_or_else(|| ImportError::Obj(line, "not enough values".to_string()))?;
+        *x = part
+            .parse()
+            .map_err(|e| ImportError::Obj(line, format!("{e}")))?;
+    }
+    Ok(ret)
+}
+
+/// Reads a Wavefront OBJ, every `o` starts a new mesh. Polygons are fanned into triangles.
+pub fn read_obj(src: &str) -> Result<Vec<Mesh>, ImportError> {
+    let mut positions = Vec::new();
+    let mut colors = Vec::new();
+    let mut normals = Vec::new();
+    let mut tex_coords = Vec::new();
+
+    let mut ret = Vec::new();
+    let mut mesh = Mesh::default();
+    let mut remap: HashMap<(usize, Option<usize>, Option<usize>), u32> = HashMap::new();
+
+    let finish = |mesh: &mut Mesh, remap: &mut HashMap<_, _>, ret: &mut Vec<Mesh>| {
+        if !mesh.indices.is_empty() {
+            ret.push(std::mem::take(mesh));
+        }
+        remap.clear();
+    };
+
+    for (i, line) in src.lines().enumerate() {
+        let line_no = i + 1;
+        let mut parts = line.split_whitespace();
+        let Some(keyword) = parts.next() else {
+            continue;
+        };
+        let parts: Vec<&str> = parts.collect();
+        match keyword {
+            "o" => {
+                finish(&mut mesh, &mut remap, &mut ret);
+                mesh.name = parts.join(" ");
+            }
+            "v" => {
+                positions.push(parse_floats::<3>(line_no, &parts)?);
+                // some exporters put vertex colors after the position
+                if parts.len() >= 6 {
+                    let [r, g, b] = parse_floats::<3>(line_no, &parts[3..])?;
+                    colors.push([r, g, b, 1.0]);
+                }
+            }
+            "vn" => normals.push(parse_floats::<3>(line_no, &parts)?),
+            "vt" => tex_coords.push(parse_floats::<2>(line_no, &parts)?),
+            "f" => {
+                let resolve = |x: &str, len: usize| -> Result<usize, ImportError> {
+                    let index: i64 = x
+                        .parse()
+                        .map_err(|e| ImportError::Obj(line_no, format!("{e}")))?;
+                    // 1 based, negative counts from the end
+                    let index = if index < 0 {
+                        len as i64 + index
+                    } else {
+                        index - 1
+                    };
+                    if index < 0 || index as usize >= len {
+                        return Err(ImportError::Obj(line_no, format!("bad index {x}")));
+                    }
+                    Ok(index as usize)
+                };
+                let mut face = Vec::with_capacity(parts.len());
+                for part in &parts {
+                    let mut refs = part.split('/');
+                    let v = resolve(refs.next().unwrap_or_default(), positions.len())?;
+                    let vt = match refs.next() {
+                        Some(x) if !x.is_empty() => Some(resolve(x, tex_coords.len())?),
+                        _ => None,
+                    };
+                    let vn = match refs.next() {
+                        Some(x) if !x.is_empty() => Some(resolve(x, normals.len())?),
+                        _ => None,
+                    };
+                    let index = *remap.entry((v, vt, vn)).or_insert_with(|| {
+                        mesh.positions.push(positions[v]);
+                        if colors.len() == positions.len() {
+                            mesh.colors.push(colors[v]);
+                        }
+                        if let Some(vn) = vn {
+                            mesh.normals.push(normals[vn]);
+                        }
+                        if let Some(vt) = vt {
+                            if mesh.tex_coords.is_empty() {
+                                mesh.tex_coords.push(Vec::new());
+                            }
+                            mesh.tex_coords[0].push(tex_coords[vt]);
+                        }
+                        (mesh.positions.len() - 1) as u32
+                    });
+                    face.push(index);
+                }
+                if face.len() < 3 {
+                    return Err(ImportError::Obj(
+                        line_no,
+                        "face with less than 3 vertices".to_string(),
+                    ));
+                }
+                for j in 1..face.len() - 1 {
+                    mesh.indices
+                        .extend_from_slice(&[face[0], face[j], face[j + 1]]);
+                }
+            }
+            _ => {}
+        }
+        // attributes have to be there for every vertex or none at all
+        let count = mesh.positions.len();
+        if (!mesh.normals.is_empty() && mesh.normals.len() != count)
+            || mesh.tex_coords.first().is_some_and(|x| x.len() != count)
+        {
+            return Err(ImportError::Obj(line_no, "mixed face formats".to_string()));
+        }
+    }
+    finish(&mut mesh, &mut remap, &mut ret);
+    Ok(ret)
+}
+
+#[cfg(feature = "gltf")]
+fn load_buffers(
+    document: &gltf::Document,
+    blob: Option<Vec<u8>>,
+    base: Option<&std::path::Path>,
+) -> Result<Vec<Vec<u8>>, ImportError> {
+    use base64::Engine;
+
+    let mut blob = blob;
+    let mut ret = Vec::new();
+    for buffer in document.buffers() {
+        let data = match buffer.source() {
+            gltf::buffer::Source::Bin => blob.take().ok_or(ImportError::Missing("GLB blob"))?,
+            gltf::buffer::Source::Uri(uri) => {
+                if let Some(data) = uri.strip_prefix("data:") {
+                    let (_, data) = data
+                        .split_once(";base64,")
+                        .ok_or(ImportError::Missing("base64 data"))?;
+                    base64::engine::general_purpose::STANDARD.decode(data)?
+                } else {
+                    let base = base.ok_or(ImportError::Missing("base path"))?;
+                    std::fs::read(base.join(uri))?
+                }
+            }
+        };
+        ret.push(data);
+    }
+    Ok(ret)
+}
+
+/// Reads every triangle primitive of a glTF or GLB, `base` is where external buffers live.
+#[cfg(feature = "gltf")]
+pub fn read_gltf(src: &[u8], base: Option<&std::path::Path>) -> Result<Vec<Mesh>, ImportError> {
+    let gltf::Gltf { document, blob } = gltf::Gltf::from_slice(src)?;
+    let buffers = load_buffers(&document, blob, base)?;
+
+    let mut ret = Vec::new();
+    for mesh in document.meshes() {
+        for (i, primitive) in mesh.primitives().enumerate() {
+            let reader = primitive.reader(|b| buffers.get(b.index()).map(Vec::as_slice));
+            let positions: Vec<[f32; 3]> = reader
+                .read_positions()
+                .ok_or(ImportError::Missing("POSITION"))?
+                .collect();
+            let indices: Vec<u32> = match reader.read_indices() {
+                Some(x) => x.into_u32().collect(),
+                None => (0..positions.len() as u32).collect(),
+            };
+            let indices = match primitive.mode() {
+                gltf::mesh::Mode::Triangles => indices,
+                gltf::mesh::Mode::TriangleStrip => (0..indices.len().saturating_sub(2))
+                    .flat_map(|j| {
+                        if j & 1 == 0 {
+                            [indices[j], indices[j + 1], indices[j + 2]]
+                        } else {
+                            [indices[j + 1], indices[j], indices[j + 2]]
+                        }
+                    })
+                    .collect(),
+                gltf::mesh::Mode::TriangleFan => (1..indices.len().saturating_sub(1))
+                    .flat_map(|j| [indices[0], indices[j], indices[j + 1]])
+                    .collect(),
+                x => return Err(ImportError::UnsupportedMode(format!("{x:?}"))),
+            };
+
+            let mut tex_coords = Vec::new();
+            while let Some(set) = reader.read_tex_coords(tex_coords.len() as u32) {
+                tex_coords.push(set.into_f32().collect());
+                if tex_coords.len() == 8 {
+                    break;
+                }
+            }
+            let name = mesh.name().unwrap_or("mesh").to_string();
+            ret.push(Mesh {
+                name: match i {
+                    0 => name,
+                    i => format!("{name}_{i}"),
+                },
+                positions,
+                normals: reader
+                    .read_normals()
+                    .map(|x| x.collect())
+                    .unwrap_or_default(),
+                colors: reader
+                    .read_colors(0)
+                    .map(|x| x.into_rgba_f32().collect())
+                    .unwrap_or_default(),
+                weights: reader
+                    .read_weights(0)
+                    .map(|x| x.into_f32().collect())
+                    .unwrap_or_default(),
+                joints: reader
+                    .read_joints(0)
+                    .map(|x| x.into_u16().map(|j| j.map(u32::from)).collect())
+                    .unwrap_or_default(),
+                tex_coords,
+                indices,
+            });
+        }
+    }
+    Ok(ret)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn triangle() -> Mesh {
+        Mesh {
+            name: "tri".to_string(),
+            positions: vec![[0.0; 3], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]],
+            indices: vec![0, 1, 2],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn writes_every_attribute() {
+        let mut mesh = triangle();
+        mesh.normals = vec![[0.0, 0.0, 1.0]; 3];
+        mesh.weights = vec![[1.0, 0.0, 0.0, 0.0]; 3];
+        mesh.joints = vec![[0; 4]; 3];
+        mesh.tex_coords = vec![vec![[0.0; 2]; 3]];
+        let geometry = mesh.to_geometry(&ImportOptions::default()).unwrap();
+        assert_eq!(geometry.len(), 1);
+        let size = geometry[0].vertex_bitmask.vertex_size();
+        assert_eq!(geometry[0].vertex_data.as_ref().unwrap().len(), 3 * size);
+    }
+
+    #[test]
+    fn bad_meshes_are_errors() {
+        let options = ImportOptions::default();
+        let mut mesh = triangle();
+        mesh.weights = vec![[1.0, 0.0, 0.0, 0.0]; 3];
+        assert!(matches!(
+            mesh.to_geometry(&options),
+            Err(ImportError::AttributeCount { name: "joints", .. })
+        ));
+
+        let mut mesh = triangle();
+        mesh.normals = vec![[0.0, 0.0, 1.0]; 2];
+        assert!(matches!(
+            mesh.to_geometry(&options),
+            Err(ImportError::AttributeCount {
+                name: "normals",
+                len: 2,
+                vertices: 3
+            })
+        ));
+
+        let mut mesh = triangle();
+        mesh.tex_coords = vec![vec![[0.0; 2]; 3], vec![[0.0; 2]; 4]];
+        assert!(mesh.to_geometry(&options).is_err());
+
+        let mut mesh = triangle();
+        mesh.indices = vec![0, 1, 3];
+        assert!(matches!(
+            mesh.to_geometry(&options),
+            Err(ImportError::BadIndex(3, 3))
+        ));
+
+        let mut mesh = triangle();
+        mesh.indices.push(0);
+        assert!(matches!(
+            mesh.to_geometry(&options),
+            Err(ImportError::IndexCount(4))
+        ));
+    }
+
+    // A quad fanned from the end of the list, then a triangle with every attribute
+    const OBJ: &str = "
+o square
+v 0 0 0
+v 1 0 0
+v 1 1 0
+v 0 1 0
+vn 0 0 1
+f -4//1 -3//1 -2//1 -1//1
+
+o tri
+v 0 0 1
+v 1 0 1
+v 0 1 1
+vt 0 0
+vt 1 0
+vt 0 1
+vn 0 0 -1
+f 5/1/2 6/2/2 7/3/2
+f -3/-3/-1 -1/-1/-1 -2/-2/-1
+";
+
+    #[test]
+    fn reads_obj() {
+        let meshes = read_obj(OBJ).unwrap();
+        assert_eq!(meshes.len(), 2);
+
+        let square = &meshes[0];
+        assert_eq!(square.name, "square");
+        assert_eq!(
+            square.positions,
+            [
+                [0.0, 0.0, 0.0],
+                [1.0, 0.0, 0.0],
+                [1.0, 1.0, 0.0],
+                [0.0, 1.0, 0.0]
+            ]
+        );
+        assert_eq!(square.normals, [[0.0, 0.0, 1.0]; 4]);
+        assert!(square.tex_coords.is_empty());
+        assert_eq!(square.indices, [0, 1, 2, 0, 2, 3]);
+
+        let tri = &meshes[1];
+        assert_eq!(tri.name, "tri");
+        assert_eq!(
+            tri.positions,
+            [[0.0, 0.0, 1.0], [1.0, 0.0, 1.0], [0.0, 1.0, 1.0]]
+        );
+        assert_eq!(tri.normals, [[0.0, 0.0, -1.0]; 3]);
+        assert_eq!(tri.tex_coords, [vec![[0.0, 0.0], [1.0, 0.0], [0.0, 1.0]]]);
+        // the same corners again, so no new vertices
+        assert_eq!(tri.indices, [0, 1, 2, 0, 2, 1]);
+        for mesh in &meshes {
+            mesh.validate().unwrap();
+        }
+    }
+
+    #[test]
+    fn bad_obj_is_an_error() {
+        let bad = [
+            "v 0 0 0\nf 1 2 3",
+            "v 0 0 0\nv 1 0 0\nf 1 -3 2",
+            "v 0 0 0\nv 1 0 0\nf 1 2",
+            "v 0 0\n",
+            "v 0 0 0\nv 1 0 0\nv 0 1 0\nvn 0 0 1\nf 1//1 2//1 3//1\nf 1 2 3",
+        ];
+        for src in bad {
+            assert!(
+                matches!(read_obj(src), Err(ImportError::Obj(..))),
+                "{src:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn splits_big_meshes() {
+        // every triangle has its own vertices, found again by their x
+        let count = 30000;
+        let mesh = Mesh {
+            name: "big".to_string(),
+            positions: (0..count * 3).map(|i| [i as f32, 0.0, 0.0]).collect(),
+            indices: (0..count * 3).map(|i| i as u32).collect(),
+            ..Default::default()
+        };
+        assert!(mesh.positions.len() > MAX_VERTICES);
+        let options = ImportOptions {
+            strip: false,
+            source: Conversion::d3d(),
+        };
+        let geometry = mesh.to_geometry(&options).unwrap();
+        assert_eq!(geometry.len(), 2);
+        assert_eq!(geometry[1].name, "big_1");
+
+        let mut triangles = Vec::new();
+        for g in &geometry {
+            let vertices = g.vertex_num.unwrap() as usize;
+            assert!(vertices <= MAX_VERTICES);
+            let positions = g.positions().unwrap();
+            assert_eq!(positions.len(), vertices);
+            for t in g.idk[0].triangles() {
+                triangles.push(t.map(|x| positions[x as usize][0] as u32));
+            }
+        }
+        triangles.sort_unstable();
+        let expected: Vec<[u32; 3]> = (0..count as u32)
+            .map(|i| [3 * i, 3 * i + 1, 3 * i + 2])
+            .collect();
+        assert_eq!(triangles, expected);
+    }
+}
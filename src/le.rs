@@ -0,0 +1,205 @@
+// Everything the game writes is little endian, whatever the host is
+
+/// Fixed size values as stored in the files.
+pub trait Le: Copy {
+    const SIZE: usize;
+
+    // `bytes` is at least SIZE long
+    fn from_le(bytes: &[u8]) -> Self;
+    fn to_le(self, out: &mut [u8]);
+}
+
+macro_rules! impl_le {
+    ($($t:ty),*) => {
+        $(
+            impl Le for $t {
+                const SIZE: usize = std::mem::size_of::<$t>();
+
+                fn from_le(bytes: &[u8]) -> Self {
+                    let mut buf = [0; std::mem::size_of::<$t>()];
+                    buf.copy_from_slice(&bytes[..Self::SIZE]);
+                    <$t>::from_le_bytes(buf)
+                }
+
+                fn to_le(self, out: &mut [u8]) {
+                    out[..Self::SIZE].copy_from_slice(&self.to_le_bytes());
+                }
+            }
+        )*
+    };
+}
+
+impl_le!(u8, i8, u16, i16, u32, i32, u64, i64, f32, f64);
+
+impl<T: Le, const N: usize> Le for [T; N] {
+    const SIZE: usize = T::SIZE * N;
+
+    fn from_le(bytes: &[u8]) -> Self {
+        std::array::from_fn(|i| T::from_le(&bytes[i * T::SIZE..]))
+    }
+
+    fn to_le(self, out: &mut [u8]) {
+        for (i, x) in self.into_iter().enumerate() {
+            x.to_le(&mut out[i * T::SIZE..]);
+        }
+    }
+}
+
+/// Panics if `src` is too short, like indexing would.
+pub fn read<T: Le>(src: &[u8], at: usize) -> T {
+    T::from_le(&src[at..at + T::SIZE])
+}
+
+pub fn get<T: Le>(src: &[u8], at: usize) -> Option<T> {
+    src.get(at..at.checked_add(T::SIZE)?).map(T::from_le)
+}
+
+pub fn write<T: Le>(dst: &mut [u8], at: usize, value: T) {
+    value.to_le(&mut dst[at..at + T::SIZE]);
+}
+
+pub fn push<T: Le>(out: &mut Vec<u8>, value: T) {
+    let at = out.len();
+    out.resize(at + T::SIZE, 0);
+    value.to_le(&mut out[at..]);
+}
+
+pub fn push_all<T: Le>(out: &mut Vec<u8>, values: &[T]) {
+    for &x in values {
+        push(out, x);
+    }
+}
+
+/// Every `T` in `src`, a partial one at the end is ignored.
+pub fn read_all<T: Le + 'static>(src: &[u8]) -> impl Iterator<Item = T> + '_ {
+    src.chunks_exact(T::SIZE).map(T::from_le)
+}
+
+/// Cursor over a byte slice, reads return None instead of running off the end.
+#[derive(Debug, Clone)]
+pub struct Reader<'a> {
+    src: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(src: &'a [u8]) -> Self {
+        Self { src, pos: 0 }
+    }
+
+    pub fn position(&self) -> usize {
+        self.pos
+    }
+
+    pub fn remaining(&self) -> &'a [u8] {
+        &self.src[self.pos..]
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pos == self.src.len()
+    }
+
+    pub fn read<T: Le>(&mut self) -> Option<T> {
+        let ret = get(self.src, self.pos)?;
+        self.pos += T::SIZE;
+        Some(ret)
+    }
+
+    pub fn bytes(&mut self, len: usize) -> Option<&'a [u8]> {
+        let ret = self.src.get(self.pos..self.pos.checked_add(len)?)?;
+        self.pos += len;
+        Some(ret)
+    }
+
+    pub fn skip(&mut self, len: usize) -> Option<()> {
+        self.bytes(len).map(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 0x1234, 0x89abcdef, 1.5, then [1, 2] as u16s
+    const LE: [u8; 14] = [
+        0x34, 0x12, 0xef, 0xcd, 0xab, 0x89, 0x00, 0x00, 0xc0, 0x3f, 0x01, 0x00, 0x02, 0x00,
+    ];
+
+    // Each value with its bytes reversed, what a big endian file would have
+    fn swapped() -> Vec<u8> {
+        let mut ret = LE.to_vec();
+        ret[0..2].reverse();
+        ret[2..6].reverse();
+        ret[6..10].reverse();
+        ret[10..12].reverse();
+        ret[12..14].reverse();
+        ret
+    }
+
+    #[test]
+    fn reads_little_endian() {
+        assert_eq!(read::<u16>(&LE, 0), 0x1234);
+        assert_eq!(read::<u32>(&LE, 2), 0x89ab_cdef);
+        assert_eq!(read::<f32>(&LE, 6), 1.5);
+        assert_eq!(read::<[u16; 2]>(&LE, 10), [1, 2]);
+        assert_eq!(get::<u32>(&LE, 10), Some(0x0002_0001));
+        assert_eq!(get::<u32>(&LE, 11), None);
+        assert_eq!(get::<u8>(&LE, usize::MAX), None);
+
+        let mut r = Reader::new(&LE);
+        assert_eq!(r.read::<u16>(), Some(0x1234));
+        assert_eq!(r.read::<u32>(), Some(0x89ab_cdef));
+        assert_eq!(r.read::<f32>(), Some(1.5));
+        assert_eq!(r.read::<[u16; 2]>(), Some([1, 2]));
+        assert!(r.is_empty());
+        assert_eq!(r.read::<u8>(), None);
+        assert_eq!(r.position(), LE.len());
+    }
+
+    #[test]
+    fn swapped_bytes_read_differently() {
+        let be = swapped();
+        assert_eq!(read::<u16>(&be, 0), 0x3412);
+        assert_eq!(read::<u32>(&be, 2), 0xefcd_ab89);
+        assert_eq!(read::<f32>(&be, 6).to_bits(), 0x0000_c03f);
+        assert_eq!(read::<[u16; 2]>(&be, 10), [0x100, 0x200]);
+
+        let mut r = Reader::new(&be);
+        assert_eq!(r.read::<u16>().map(u16::swap_bytes), Some(0x1234));
+        assert_eq!(r.read::<u32>().map(u32::swap_bytes), Some(0x89ab_cdef));
+        assert_eq!(
+            r.read::<u32>().map(|x| f32::from_bits(x.swap_bytes())),
+            Some(1.5)
+        );
+        assert_eq!(
+            r.read::<[u16; 2]>().map(|x| x.map(u16::swap_bytes)),
+            Some([1, 2])
+        );
+    }
+
+    #[test]
+    fn writes_what_it_reads() {
+        let mut out = Vec::new();
+        push(&mut out, 0x1234u16);
+        push(&mut out, 0x89ab_cdefu32);
+        push(&mut out, 1.5f32);
+        push_all(&mut out, &[1u16, 2]);
+        assert_eq!(out, LE);
+
+        let mut out = [0; 14];
+        write(&mut out, 0, 0x1234u16);
+        write(&mut out, 2, 0x89ab_cdefu32);
+        write(&mut out, 6, 1.5f32);
+        write(&mut out, 10, [1u16, 2]);
+        assert_eq!(out, LE);
+
+        let be = swapped();
+        let mut out = Vec::new();
+        push(&mut out, read::<u16>(&be, 0));
+        push(&mut out, read::<u32>(&be, 2));
+        push(&mut out, read::<f32>(&be, 6));
+        push(&mut out, read::<[u16; 2]>(&be, 10));
+        assert_eq!(out, be);
+        assert_eq!(read_all::<u16>(&LE[10..]).collect::<Vec<_>>(), [1, 2]);
+    }
+}
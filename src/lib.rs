@@ -1,49 +1,500 @@
 use thiserror::Error;
 
+pub mod anim;
+pub mod bounds;
+pub mod convert;
+pub mod import;
+pub mod le;
+pub mod math;
+#[cfg(feature = "mmap")]
+pub mod mmap;
+pub mod model;
+pub mod name;
+pub mod pak;
+pub mod skin;
+pub mod sound;
+pub mod texture;
+
 pub mod bin {
     use super::*;
 
     #[derive(Error, Debug)]
     pub enum DecryptError {
-        #[error("data too small, should be at least 4 bytes")]
+        #[error("data too small, got {0} bytes")]
         TooSmall(usize),
+        #[error("decrypted data doesn't match the expected header at offset {0}")]
+        HeaderMismatch(usize),
+        #[error("expected {expected:?} data, got {found:?}")]
+        WrongKind { expected: FileKind, found: FileKind },
+        #[error("{0:?} data looks corrupted around offset {1}")]
+        Corrupted(FileKind, usize),
+        #[error("{len} bytes at offset {offset} don't fit in {size} bytes")]
+        OutOfBounds {
+            offset: usize,
+            len: usize,
+            size: usize,
+        },
+        #[error(transparent)]
+        Io(#[from] std::io::Error),
+    }
+
+    // The key cycles from the start of the archive, so anything works: short or unaligned
+    // inputs just use the key bytes their position lands on.
+    pub fn decrypt_in_place(buf: &mut [u8], key: u32, offset: usize) {
+        let key = key.to_le_bytes();
+        for (x, y) in buf.iter_mut().zip(key.iter().cycle().skip(offset % 4)) {
+            *x ^= y;
+        }
+    }
+
+    /// Decrypts `src` which starts `offset` bytes into the archive.
+    pub fn decrypt_at(src: &[u8], key: u32, offset: usize) -> Vec<u8> {
+        let mut ret = src.to_vec();
+        decrypt_in_place(&mut ret, key, offset);
+        ret
+    }
+
+    pub fn decrypt(src: &[u8], key: u32) -> Vec<u8> {
+        decrypt_at(src, key, 0)
+    }
+
+    pub fn decrypt_file<P: AsRef<std::path::Path>>(
+        path: P,
+        key: u32,
+    ) -> Result<Vec<u8>, DecryptError> {
+        let mut ret = std::fs::read(path)?;
+        decrypt_in_place(&mut ret, key, 0);
+        Ok(ret)
     }
 
+    /// Decrypts `src` and makes sure the result starts like `hint` says it should.
+    pub fn decrypt_checked(src: &[u8], key: u32, hint: &Hint) -> Result<Vec<u8>, DecryptError> {
+        let ret = decrypt(src, key);
+        hint.check(&ret)?;
+        Ok(ret)
+    }
+
+    #[deprecated(note = "use `decrypt`")]
     pub fn decrpyt(src: &[u8], key: u32) -> Result<Vec<u8>, DecryptError> {
-        if src.len() < 4 {
-            // This should never happen???
-            Err(DecryptError::TooSmall(src.len()))
-        } else {
-            let key = key.to_le_bytes();
-            Ok(src
+        Ok(decrypt(src, key))
+    }
+
+    // I will not make useless file extraction because it will make this code dependant on pelite!
+
+    // How many bytes from the start of the archive are looked at when scoring keys
+    const SCORE_WINDOW: usize = 64 * 1024;
+    // How many of the most common bytes of a lane are tried when the hint doesn't cover it
+    const FREQ_CANDIDATES: usize = 4;
+
+    /// Known plaintext at `offset` of the decrypted data, `None` bytes match anything.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct Hint {
+        pub offset: usize,
+        pub bytes: Vec<Option<u8>>,
+    }
+
+    impl Hint {
+        pub fn new(offset: usize, bytes: &[u8]) -> Self {
+            Self {
+                offset,
+                bytes: bytes.iter().copied().map(Some).collect(),
+            }
+        }
+
+        pub fn masked(offset: usize, bytes: &[Option<u8>]) -> Self {
+            Self {
+                offset,
+                bytes: bytes.to_vec(),
+            }
+        }
+
+        // First block header: type is tiny and nothing is bigger than 16MiB.
+        pub fn hgm() -> Self {
+            Self::masked(
+                0,
+                &[None, Some(0), Some(0), Some(0), None, None, None, Some(0)],
+            )
+        }
+
+        // No image id, no colormap, origin at 0,0.
+        pub fn tga() -> Self {
+            let mut bytes = vec![Some(0); 12];
+            bytes[2] = None;
+            Self::masked(0, &bytes)
+        }
+
+        // Same with a colormap: its length and depth can be anything, it usually starts at 0.
+        pub fn tga_mapped() -> Self {
+            let mut bytes = vec![Some(0); 12];
+            bytes[1] = Some(1);
+            bytes[2] = None;
+            for b in &mut bytes[5..8] {
+                *b = None;
+            }
+            Self::masked(0, &bytes)
+        }
+
+        pub fn wav() -> Self {
+            let mut bytes = vec![None; 16];
+            for (i, &b) in b"RIFF".iter().enumerate() {
+                bytes[i] = Some(b);
+            }
+            for (i, &b) in b"WAVEfmt ".iter().enumerate() {
+                bytes[8 + i] = Some(b);
+            }
+            Self::masked(0, &bytes)
+        }
+
+        // Magic, then the reserved fields which are always 0.
+        pub fn bmp() -> Self {
+            let mut bytes = vec![None; 10];
+            bytes[0] = Some(b'B');
+            bytes[1] = Some(b'M');
+            for b in &mut bytes[6..10] {
+                *b = Some(0);
+            }
+            Self::masked(0, &bytes)
+        }
+
+        pub fn all() -> [Self; 5] {
+            [
+                Self::hgm(),
+                Self::tga(),
+                Self::tga_mapped(),
+                Self::wav(),
+                Self::bmp(),
+            ]
+        }
+
+        /// Checks already decrypted data against the hint.
+        pub fn check(&self, src: &[u8]) -> Result<(), DecryptError> {
+            if src.len() < self.offset + self.bytes.len() {
+                return Err(DecryptError::TooSmall(src.len()));
+            }
+            match self
+                .bytes
                 .iter()
-                .zip(key.iter().cycle())
-                .map(|(&x, &y)| x ^ y)
-                .collect())
+                .zip(&src[self.offset..])
+                .position(|(b, x)| b.is_some_and(|b| b != *x))
+            {
+                Some(i) => Err(DecryptError::HeaderMismatch(self.offset + i)),
+                None => Ok(()),
+            }
+        }
+
+        // Key bytes implied by this hint for every lane of the 4 byte key.
+        fn lanes(&self, src: &[u8]) -> Option<[Option<u8>; 4]> {
+            let mut lanes = [None; 4];
+            for (i, b) in self.bytes.iter().enumerate() {
+                let Some(b) = b else {
+                    continue;
+                };
+                let pos = self.offset + i;
+                let k = src.get(pos)? ^ b;
+                match lanes[pos % 4] {
+                    Some(x) if x != k => return None,
+                    _ => lanes[pos % 4] = Some(k),
+                }
+            }
+            Some(lanes)
         }
     }
 
-    // I will not make useless file extraction because it will make this code dependant on pelite!
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub struct KeyCandidate {
+        pub key: u32,
+        // Share of zero bytes after decryption, plaintext is full of them
+        pub score: f32,
+    }
+
+    fn frequent_bytes(src: &[u8], lane: usize) -> Vec<u8> {
+        let mut counts = [0usize; 256];
+        for &b in src.iter().take(SCORE_WINDOW).skip(lane).step_by(4) {
+            counts[b as usize] += 1;
+        }
+        let mut order: Vec<u8> = (0..=255).collect();
+        order.sort_by_key(|&b| std::cmp::Reverse(counts[b as usize]));
+        order.truncate(FREQ_CANDIDATES);
+        order
+    }
+
+    fn score_key(src: &[u8], key: u32) -> f32 {
+        let window = &src[..src.len().min(SCORE_WINDOW)];
+        if window.is_empty() {
+            return 0.0;
+        }
+        let key = key.to_le_bytes();
+        let zeros = window
+            .iter()
+            .zip(key.iter().cycle())
+            .filter(|(&x, &y)| x == y)
+            .count();
+        zeros as f32 / window.len() as f32
+    }
+
+    /// Derives candidate keys for an encrypted archive from known plaintext, best first.
+    pub fn recover_key(src: &[u8], hints: &[Hint]) -> Vec<KeyCandidate> {
+        let mut keys = Vec::new();
+        for hint in hints {
+            let Some(lanes) = hint.lanes(src) else {
+                continue;
+            };
+            let options: Vec<Vec<u8>> = lanes
+                .iter()
+                .enumerate()
+                .map(|(lane, k)| match k {
+                    Some(k) => vec![*k],
+                    None => frequent_bytes(src, lane),
+                })
+                .collect();
+            for &a in &options[0] {
+                for &b in &options[1] {
+                    for &c in &options[2] {
+                        for &d in &options[3] {
+                            keys.push(u32::from_le_bytes([a, b, c, d]));
+                        }
+                    }
+                }
+            }
+        }
+        keys.sort_unstable();
+        keys.dedup();
+
+        let mut ret: Vec<KeyCandidate> = keys
+            .into_iter()
+            .map(|key| KeyCandidate {
+                key,
+                score: score_key(src, key),
+            })
+            .collect();
+        ret.sort_by(|a, b| b.score.total_cmp(&a.score));
+        ret
+    }
+
+    /// What a decrypted buffer looks like.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum FileKind {
+        Hgm,
+        Hga,
+        Tga,
+        Bmp,
+        Wav,
+        Unknown,
+    }
+
+    impl FileKind {
+        pub fn extension(self) -> Option<&'static str> {
+            match self {
+                Self::Hgm => Some("hgm"),
+                Self::Hga => Some("hga"),
+                Self::Tga => Some("tga"),
+                Self::Bmp => Some("bmp"),
+                Self::Wav => Some("wav"),
+                Self::Unknown => None,
+            }
+        }
+
+        /// Walks the whole buffer instead of looking only at the header like [`sniff`].
+        pub fn check(self, src: &[u8]) -> Result<(), DecryptError> {
+            let found = sniff(src);
+            if found != self {
+                return Err(DecryptError::WrongKind {
+                    expected: self,
+                    found,
+                });
+            }
+            let u32_at = |i: usize| le::read::<u32>(src, i) as usize;
+            match self {
+                Self::Hgm | Self::Hga => {
+                    chunk_chain(src).map_err(|at| DecryptError::Corrupted(self, at))?;
+                }
+                Self::Tga => {
+                    let u16_at = |i: usize| le::read::<u16>(src, i);
+                    let cmap_size = u16_at(5) as usize * (src[7] as usize).div_ceil(8);
+                    let pixels = u16_at(12) as usize * u16_at(14) as usize;
+                    let mut size = 18 + src[0] as usize + cmap_size;
+                    // RLE can't be checked without decoding it
+                    if src[2] < 8 {
+                        size += pixels * (src[16] as usize).div_ceil(8);
+                    }
+                    if size > src.len() {
+                        return Err(DecryptError::Corrupted(self, src.len()));
+                    }
+                }
+                Self::Bmp => {
+                    if u32_at(2) > src.len() {
+                        return Err(DecryptError::Corrupted(self, 2));
+                    }
+                    if u32_at(10) >= src.len() {
+                        return Err(DecryptError::Corrupted(self, 10));
+                    }
+                }
+                Self::Wav => {
+                    if u32_at(4) + 8 > src.len() {
+                        return Err(DecryptError::Corrupted(self, 4));
+                    }
+                }
+                Self::Unknown => {}
+            }
+            Ok(())
+        }
+    }
+
+    // Offset of the first chunk that doesn't fit, the chain has to end exactly at the end of `src`
+    fn chunk_chain(src: &[u8]) -> Result<(), usize> {
+        let mut cursor = 0;
+        while cursor < src.len() {
+            let (Some(typ), Some(size)) =
+                (le::get::<u32>(src, cursor), le::get::<u32>(src, cursor + 4))
+            else {
+                return Err(cursor);
+            };
+            let size = size as usize;
+            if typ > 11 || size < 8 || size > src.len() - cursor {
+                return Err(cursor);
+            }
+            cursor += size;
+        }
+        Ok(())
+    }
+
+    /// Guesses the kind of decrypted data from its header, the first 64 bytes are enough
+    /// except for hgm and hga: those need the whole file, every chunk has to fit.
+    pub fn sniff(src: &[u8]) -> FileKind {
+        if src.len() >= 12 && &src[..4] == b"RIFF" && &src[8..12] == b"WAVE" {
+            return FileKind::Wav;
+        }
+        if src.len() >= 26 && &src[..2] == b"BM" && src[6..10] == [0; 4] {
+            return FileKind::Bmp;
+        }
+        if src.len() >= 8 {
+            let typ = le::read::<u32>(src, 0);
+            let size = le::read::<u32>(src, 4);
+            if typ <= 11 && size >= 8 && chunk_chain(src).is_ok() {
+                // guess: .hga use the same chunks but start with animation ones
+                return match typ {
+                    5..=7 => FileKind::Hga,
+                    _ => FileKind::Hgm,
+                };
+            }
+        }
+        if src.len() >= 18 {
+            let cmap_type = src[1];
+            let img_type = src[2];
+            let cmap_depth = src[7];
+            let width = le::read::<u16>(src, 12);
+            let height = le::read::<u16>(src, 14);
+            let depth = src[16];
+            let indexed = matches!(img_type, 1 | 9);
+            if matches!(img_type, 1 | 2 | 3 | 9 | 10 | 11)
+                && cmap_type == indexed as u8
+                && (!indexed || matches!(cmap_depth, 15 | 16 | 24 | 32))
+                && matches!(depth, 8 | 15 | 16 | 24 | 32)
+                && width != 0
+                && height != 0
+            {
+                return FileKind::Tga;
+            }
+        }
+        FileKind::Unknown
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        // 4x4 8 bit colour-mapped image with a 16 entry 24 bit palette
+        fn mapped_tga() -> Vec<u8> {
+            let mut ret = vec![0, 1, 1, 0, 0, 16, 0, 24, 0, 0, 0, 0, 4, 0, 4, 0, 8, 0];
+            ret.extend((0..16u8).flat_map(|i| [i * 16, i * 8, 0]));
+            ret.extend([0u8; 16]);
+            ret
+        }
+
+        #[test]
+        fn recovers_colour_mapped_tga_key() {
+            let key = 0xDEADBEEF;
+            let mut data = mapped_tga();
+            assert_eq!(sniff(&data), FileKind::Tga);
+            decrypt_in_place(&mut data, key, 0);
+
+            assert!(recover_key(&data, &[Hint::tga()])
+                .iter()
+                .all(|x| x.key != key));
+            let found = recover_key(&data, &[Hint::tga_mapped()]);
+            assert_eq!(found.first().map(|x| x.key), Some(key));
+            assert!(recover_key(&data, &Hint::all())
+                .iter()
+                .any(|x| x.key == key));
+        }
+
+        fn chunk(typ: u32, payload: usize) -> Vec<u8> {
+            let mut ret = Vec::new();
+            le::push(&mut ret, typ);
+            le::push(&mut ret, payload as u32 + 8);
+            ret.resize(payload + 8, 0);
+            ret
+        }
+
+        #[test]
+        fn sniffs_whole_chunk_chains() {
+            let mut data = chunk(4, 16);
+            data.extend(chunk(0, 4));
+            assert_eq!(sniff(&data), FileKind::Hgm);
+            assert!(FileKind::Hgm.check(&data).is_ok());
+            let anim = [chunk(7, 8), chunk(6, 0)].concat();
+            assert_eq!(sniff(&anim), FileKind::Hga);
+
+            // a plausible first header isn't enough
+            assert_eq!(sniff(&data[..data.len() - 1]), FileKind::Unknown);
+            let mut bad = data.clone();
+            bad[24] = 12;
+            assert_eq!(sniff(&bad), FileKind::Unknown);
+            let mut bad = data.clone();
+            bad.extend([0; 4]);
+            assert_eq!(sniff(&bad), FileKind::Unknown);
+            // random bytes with a small first u32
+            let mut bad = vec![3, 0, 0, 0, 0x20, 0, 0, 0];
+            bad.extend((0..56u8).map(|x| x.wrapping_mul(97)));
+            assert_eq!(sniff(&bad), FileKind::Unknown);
+        }
+    }
 }
 
 pub mod hg {
     use bitmask::bitmask;
-    use std::str::Utf8Error;
+    use std::borrow::Cow;
+    use thiserror::Error;
+
+    use crate::anim::Transform;
+    use crate::le::{self, Le, Reader};
+    use crate::math::{self, EulerOrder, Mat4};
+    use crate::name::Name;
+
+    #[cfg(feature = "serde")]
+    mod serialize;
+    pub mod strip;
+    mod writer;
+    pub use writer::{write_blocks, WriteError};
 
-    #[derive(Debug)]
+    #[derive(Debug, Clone)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
     pub struct TRS3d {
         pub pos: [f32; 3],
         pub rot: [f32; 3],
         pub scale: [f32; 3],
     }
 
-    #[derive(Debug, Eq, PartialEq)]
+    // Only triangles have shown up in files, as 0..=2. That's not D3DPRIMITIVETYPE's numbering
+    // (PointList 1, LineList 2, LineStrip 3, then triangles from 4), so there's no telling what
+    // other values mean and they're kept raw until a file shows one
+    #[derive(Debug, Clone, Copy, Eq, PartialEq)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
     pub enum PTEnum {
-        // TODO: is start at 0?
-        TriangleList = 0,
-        TriangleStrip = 1,
-        TriangleFan = 2,
-        UNK,
+        TriangleList,
+        TriangleStrip,
+        TriangleFan,
+        Unknown(u32),
     }
 
     impl From<u32> for PTEnum {
@@ -52,187 +503,229 @@ pub mod hg {
                 0 => Self::TriangleList,
                 1 => Self::TriangleStrip,
                 2 => Self::TriangleFan,
-                _ => Self::UNK,
+                x => Self::Unknown(x),
             }
         }
     }
 
-    #[derive(Debug)]
-    pub struct GeometryBlockInner {
-        pub typ: PTEnum,
-        pub words: Vec<u16>,
+    impl From<&PTEnum> for u32 {
+        fn from(value: &PTEnum) -> Self {
+            match value {
+                PTEnum::TriangleList => 0,
+                PTEnum::TriangleStrip => 1,
+                PTEnum::TriangleFan => 2,
+                PTEnum::Unknown(x) => *x,
+            }
+        }
     }
 
-    bitmask! {
-        // #[derive(Debug)]
-        pub mask VertexMask: u32
-        where flags VertexFeatures {
-            Position        = 1<<0,
-            Normal          = 1<<1,
-            Tangent         = 1<<2,
-            Binormal        = 1<<3,
-
-            Color0          = 1<<4,
-            Color1          = 1<<5,
+    impl PTEnum {
+        /// D3DPRIMITIVETYPE value.
+        pub fn to_d3d(&self) -> Option<u32> {
+            match self {
+                Self::TriangleList => Some(4),
+                Self::TriangleStrip => Some(5),
+                Self::TriangleFan => Some(6),
+                Self::Unknown(_) => None,
+            }
+        }
 
-            Weight0         = 1<<6,
-            Weight1         = 1<<7,
-            Weight2         = 1<<8,
-            Weight3         = 1<<9,
-            WeightIndicies  = 1<<10,
+        pub fn is_triangles(&self) -> bool {
+            matches!(
+                self,
+                Self::TriangleList | Self::TriangleStrip | Self::TriangleFan
+            )
+        }
 
-            TexCoordinate0  = 1<<11,
-            TexCoordinate1  = 1<<12,
-            TexCoordinate2  = 1<<13,
-            TexCoordinate3  = 1<<14,
-            TexCoordinate4  = 1<<15,
-            TexCoordinate5  = 1<<16,
-            TexCoordinate6  = 1<<17,
-            TexCoordinate7  = 1<<18,
+        /// How many primitives `len` indices make.
+        pub fn primitive_count(&self, len: usize) -> usize {
+            match self {
+                Self::TriangleList => len / 3,
+                Self::TriangleStrip | Self::TriangleFan => len.saturating_sub(2),
+                Self::Unknown(_) => 0,
+            }
         }
     }
 
-    impl std::fmt::Display for VertexMask {
-        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-            let mut first = true;
-            // TODO: replace this ugly shit with a macro
-            if *(*self & VertexFeatures::Position) != 0 {
-                if !first {
-                    write!(f, " | ")?;
-                }
-                write!(f, "VertexFeatures::Position")?;
-                first = false;
-            }
-            if *(*self & VertexFeatures::Normal) != 0 {
-                if !first {
-                    write!(f, " | ")?;
-                }
-                write!(f, "VertexFeatures::Normal")?;
-                first = false;
-            }
-            if *(*self & VertexFeatures::Tangent) != 0 {
-                if !first {
-                    write!(f, " | ")?;
-                }
-                write!(f, "VertexFeatures::Tangent")?;
-                first = false;
-            }
-            if *(*self & VertexFeatures::Binormal) != 0 {
-                if !first {
-                    write!(f, " | ")?;
-                }
-                write!(f, "VertexFeatures::Binormal")?;
-                first = false;
-            }
-            if *(*self & VertexFeatures::Color0) != 0 {
-                if !first {
-                    write!(f, " | ")?;
-                }
-                write!(f, "VertexFeatures::Color0")?;
-                first = false;
-            }
-            if *(*self & VertexFeatures::Color1) != 0 {
-                if !first {
-                    write!(f, " | ")?;
-                }
-                write!(f, "VertexFeatures::Color1")?;
-                first = false;
-            }
-            if *(*self & VertexFeatures::Weight0) != 0 {
-                if !first {
-                    write!(f, " | ")?;
-                }
-                write!(f, "VertexFeatures::Weight0")?;
-                first = false;
-            }
-            if *(*self & VertexFeatures::Weight1) != 0 {
-                if !first {
-                    write!(f, " | ")?;
-                }
-                write!(f, "VertexFeatures::Weight1")?;
-                first = false;
-            }
-            if *(*self & VertexFeatures::Weight2) != 0 {
-                if !first {
-                    write!(f, " | ")?;
-                }
-                write!(f, "VertexFeatures::Weight2")?;
-                first = false;
-            }
-            if *(*self & VertexFeatures::Weight3) != 0 {
-                if !first {
-                    write!(f, " | ")?;
-                }
-                write!(f, "VertexFeatures::Weight3")?;
-                first = false;
-            }
-            if *(*self & VertexFeatures::WeightIndicies) != 0 {
-                if !first {
-                    write!(f, " | ")?;
-                }
-                write!(f, "VertexFeatures::WeightIndicies")?;
-                first = false;
-            }
-            if *(*self & VertexFeatures::TexCoordinate0) != 0 {
-                if !first {
-                    write!(f, " | ")?;
-                }
-                write!(f, "VertexFeatures::TexCoordinate0")?;
-                first = false;
-            }
-            if *(*self & VertexFeatures::TexCoordinate1) != 0 {
-                if !first {
-                    write!(f, " | ")?;
-                }
-                write!(f, "VertexFeatures::TexCoordinate1")?;
-                first = false;
-            }
-            if *(*self & VertexFeatures::TexCoordinate2) != 0 {
-                if !first {
-                    write!(f, " | ")?;
-                }
-                write!(f, "VertexFeatures::TexCoordinate2")?;
-                first = false;
-            }
-            if *(*self & VertexFeatures::TexCoordinate3) != 0 {
-                if !first {
-                    write!(f, " | ")?;
+    #[derive(Debug, Clone)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+    pub struct GeometryBlockInner {
+        pub typ: PTEnum,
+        pub words: Vec<u16>,
+    }
+
+    // One line per flag: name, bit and bytes per vertex. Attributes are laid out in bit order
+    macro_rules! vertex_features {
+        ($($name:ident = $bit:literal, $size:literal;)*) => {
+            bitmask! {
+                pub mask VertexMask: u32
+                where flags VertexFeatures {
+                    $($name = 1 << $bit,)*
                 }
-                write!(f, "VertexFeatures::TexCoordinate3")?;
-                first = false;
             }
-            if *(*self & VertexFeatures::TexCoordinate4) != 0 {
-                if !first {
-                    write!(f, " | ")?;
+
+            impl VertexFeatures {
+                pub const ALL: &'static [VertexFeatures] = &[$(VertexFeatures::$name,)*];
+
+                pub fn name(self) -> &'static str {
+                    match self {
+                        $(VertexFeatures::$name => stringify!($name),)*
+                    }
                 }
-                write!(f, "VertexFeatures::TexCoordinate4")?;
-                first = false;
-            }
-            if *(*self & VertexFeatures::TexCoordinate5) != 0 {
-                if !first {
-                    write!(f, " | ")?;
+
+                /// Bytes per vertex.
+                pub fn size(self) -> usize {
+                    match self {
+                        $(VertexFeatures::$name => $size,)*
+                    }
                 }
-                write!(f, "VertexFeatures::TexCoordinate5")?;
-                first = false;
             }
-            if *(*self & VertexFeatures::TexCoordinate6) != 0 {
-                if !first {
-                    write!(f, " | ")?;
-                }
-                write!(f, "VertexFeatures::TexCoordinate6")?;
-                first = false;
+        };
+    }
+
+    vertex_features! {
+        Position        = 0, 12;
+        Normal          = 1, 12;
+        Tangent         = 2, 12;
+        Binormal        = 3, 12;
+
+        Color0          = 4, 16;
+        Color1          = 5, 16;
+
+        Weight0         = 6, 4;
+        Weight1         = 7, 4;
+        Weight2         = 8, 4;
+        Weight3         = 9, 4;
+        WeightIndicies  = 10, 16;
+
+        TexCoordinate0  = 11, 8;
+        TexCoordinate1  = 12, 8;
+        TexCoordinate2  = 13, 8;
+        TexCoordinate3  = 14, 8;
+        TexCoordinate4  = 15, 8;
+        TexCoordinate5  = 16, 8;
+        TexCoordinate6  = 17, 8;
+        TexCoordinate7  = 18, 8;
+    }
+
+    impl VertexFeatures {
+        pub fn bit(self) -> u32 {
+            (self as u32).trailing_zeros()
+        }
+
+        pub fn from_name(name: &str) -> Option<Self> {
+            Self::ALL.iter().copied().find(|x| x.name() == name)
+        }
+    }
+
+    impl std::fmt::Debug for VertexFeatures {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "{}", self.name())
+        }
+    }
+
+    #[derive(Error, Debug)]
+    #[error("unknown vertex feature {0}")]
+    pub struct UnknownFeature(pub String);
+
+    impl VertexMask {
+        pub fn from_bits(mask: u32) -> Self {
+            Self { mask }
+        }
+
+        pub fn bits(&self) -> u32 {
+            self.mask
+        }
+
+        /// Set flags in bit order, which is also the vertex layout order.
+        pub fn iter(&self) -> impl Iterator<Item = VertexFeatures> + '_ {
+            VertexFeatures::ALL
+                .iter()
+                .copied()
+                .filter(|x| self.contains(*x))
+        }
+
+        /// Bits none of the flags cover, their size in a vertex is anyone's guess.
+        pub fn unknown_bits(&self) -> u32 {
+            let known = VertexFeatures::ALL.iter().fold(0, |acc, x| acc | *x as u32);
+            self.mask & !known
+        }
+
+        /// Flag names, bits we don't know about yet show up as "bitN".
+        pub fn names(&self) -> Vec<String> {
+            let unknown = self.unknown_bits();
+            self.iter()
+                .map(|x| x.name().to_string())
+                .chain(
+                    (0..32)
+                        .filter(|i| (unknown >> i) & 1 != 0)
+                        .map(|i| format!("bit{i}")),
+                )
+                .collect()
+        }
+
+        /// Inverse of `names`.
+        pub fn from_names<S: AsRef<str>>(names: &[S]) -> Result<Self, UnknownFeature> {
+            let mut mask = 0u32;
+            for name in names {
+                let name = name.as_ref();
+                mask |= match VertexFeatures::from_name(name) {
+                    Some(x) => x as u32,
+                    None => name
+                        .strip_prefix("bit")
+                        .and_then(|x| x.parse::<u32>().ok())
+                        .filter(|x| *x < 32)
+                        .map(|x| 1 << x)
+                        .ok_or_else(|| UnknownFeature(name.to_string()))?,
+                };
             }
-            if *(*self & VertexFeatures::TexCoordinate7) != 0 {
-                if !first {
-                    write!(f, " | ")?;
-                }
-                write!(f, "VertexFeatures::TexCoordinate7")?;
-                // first = false;
+            Ok(Self { mask })
+        }
+
+        /// Where every set flag sits in a vertex.
+        pub fn layout(&self) -> Vec<(VertexFeatures, usize)> {
+            let mut offset = 0;
+            self.iter()
+                .map(|x| {
+                    offset += x.size();
+                    (x, offset - x.size())
+                })
+                .collect()
+        }
+
+        /// Stride of one vertex.
+        pub fn vertex_size(&self) -> usize {
+            self.iter().map(VertexFeatures::size).sum()
+        }
+
+        pub fn offset_of(&self, feature: VertexFeatures) -> Option<usize> {
+            self.layout()
+                .into_iter()
+                .find(|(x, _)| *x == feature)
+                .map(|(_, offset)| offset)
+        }
+    }
+
+    // "Position|Normal|TexCoordinate0", same names as `names`
+    impl std::fmt::Display for VertexMask {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            if self.mask == 0 {
+                return write!(f, "()");
             }
-            if self.is_none() {
-                write!(f, "()")?;
+            write!(f, "{}", self.names().join("|"))
+        }
+    }
+
+    impl std::str::FromStr for VertexMask {
+        type Err = UnknownFeature;
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            let s = s.trim();
+            if s.is_empty() || s == "()" {
+                return Ok(Self::none());
             }
-            Ok(())
+            let names: Vec<&str> = s.split('|').map(str::trim).collect();
+            Self::from_names(&names)
         }
     }
 
@@ -242,9 +735,10 @@ pub mod hg {
         }
     }
 
-    #[derive(Debug)]
+    #[derive(Debug, Clone)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
     pub struct GeometryBlock<'a> {
-        pub name: &'a str,
+        pub name: Name<'a>,
         pub coords: [f32; 4],
         pub bool4: bool,
         pub vertex_bitmask: VertexMask,
@@ -254,196 +748,543 @@ pub mod hg {
 
         pub vertex_num: Option<u32>,
         pub vertex_size: Option<usize>,
-        pub vertex_data: Option<&'a [u8]>,
+        #[cfg_attr(feature = "serde", serde(with = "serialize::opt_bytes"))]
+        pub vertex_data: Option<Cow<'a, [u8]>>,
+
+        // Whatever is left in the block after the parsed fields
+        #[cfg_attr(feature = "serde", serde(with = "serialize::bytes"))]
+        pub rest: Cow<'a, [u8]>,
     }
 
-    #[derive(Debug)]
+    #[derive(Debug, Clone)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
     pub struct TransformBlock<'a> {
-        pub name: &'a str,
+        pub name: Name<'a>,
         pub idk: u32,
         // T R S
         pub coords: TRS3d,
+
+        #[cfg_attr(feature = "serde", serde(with = "serialize::bytes"))]
+        pub rest: Cow<'a, [u8]>,
     }
 
-    #[derive(Debug)]
+    #[derive(Debug, Clone)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
     pub struct BoneBlock<'a> {
-        pub name: &'a str,
+        pub name: Name<'a>,
         pub idk: u32,
         pub coords: [[f32; 3]; 3],
 
-        // X = 1, Y = 0
-        // 1 4 7 Y
-        // 2 5 8 Y
-        // 3 6 9 Y
-        // Y Y Y X
+        // D3D style, row vectors and translation in the last row.
+        // Stored as 3 columns of 4, the last column is always 0 0 0 1
         pub matrix: [[f32; 4]; 4],
+
+        #[cfg_attr(feature = "serde", serde(with = "serialize::bytes"))]
+        pub rest: Cow<'a, [u8]>,
+    }
+
+    // Blocks we don't parse yet, kept as is so they can be written back
+    #[derive(Debug, Clone)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+    pub struct RawBlock<'a> {
+        pub typ: u32,
+        #[cfg_attr(feature = "serde", serde(with = "serialize::bytes"))]
+        pub data: Cow<'a, [u8]>,
     }
 
-    #[derive(Debug)]
+    #[derive(Debug, Clone)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
     pub enum Block<'a> {
         Geometry(GeometryBlock<'a>),
-        Shader,
-        Shape,
-        Texture,
         Transform(TransformBlock<'a>),
-        Animator,
-        AnimationData,
-        AnimationSet,
-        Hierarchy,
         Bone(BoneBlock<'a>),
+        // Everything else: shader 1, shape 2, texture 3, animator 5, animation data 6,
+        // animation set 7, hierarchy 8 and whatever types files turn out to have
+        Raw(RawBlock<'a>),
     }
 
-    fn read_str<'a>(src: &'a [u8]) -> Result<(&'a str, usize), Utf8Error> {
-        let mut size = 0;
-        loop {
-            if src[size] == 0 {
-                break;
+    /// Everything borrows from nothing, for building models in memory.
+    pub type OwnedBlock = Block<'static>;
+
+    impl Block<'_> {
+        pub fn typ(&self) -> u32 {
+            match self {
+                Self::Geometry(_) => 0,
+                Self::Transform(_) => 4,
+                Self::Bone(_) => 11,
+                Self::Raw(raw) => raw.typ,
+            }
+        }
+
+        /// Copies whatever is still borrowed so the source buffer can go away.
+        pub fn into_owned(self) -> OwnedBlock {
+            match self {
+                Self::Geometry(x) => Block::Geometry(x.into_owned()),
+                Self::Transform(x) => Block::Transform(x.into_owned()),
+                Self::Bone(x) => Block::Bone(x.into_owned()),
+                Self::Raw(x) => Block::Raw(x.into_owned()),
             }
-            size += 1;
         }
-        let skip = 4 * (size / 4) + 4;
-        Ok((std::str::from_utf8(&src[..size])?, skip))
     }
 
-    pub fn read_blocks<'a>(src: &'a [u8]) -> Vec<Block<'a>> {
-        // if src.len() < 8
-        let mut ret = Vec::new();
-        let mut cursor = src;
-        loop {
-            if cursor.len() < 8 {
-                break;
+    impl GeometryBlock<'_> {
+        /// Bytes per vertex, from the mask. None when a stored `vertex_size` disagrees with it,
+        /// the mask was probably edited without it.
+        pub fn stride(&self) -> Option<usize> {
+            let size = self.vertex_bitmask.vertex_size();
+            match self.vertex_size {
+                Some(x) if x != size => None,
+                _ => Some(size),
             }
+        }
 
-            let typ = bytemuck::pod_read_unaligned::<u32>(&cursor[..4]);
-            let size = bytemuck::pod_read_unaligned::<u32>(&cursor[4..8]) as usize;
-            cursor = &cursor[8..];
+        pub fn into_owned(self) -> GeometryBlock<'static> {
+            GeometryBlock {
+                name: self.name.into_owned(),
+                vertex_data: self.vertex_data.map(|x| Cow::Owned(x.into_owned())),
+                rest: Cow::Owned(self.rest.into_owned()),
+                ..self
+            }
+        }
+    }
 
-            eprintln!("{} {} {}", typ, size, cursor.len());
-            if typ > 11 {
-                break;
-            }
-
-            match typ {
-                4 => {
-                    // Transform block
-                    let data = &cursor[..size - 8];
-                    let (name, skip) = read_str(data).unwrap();
-                    let data = &data[skip..];
-                    let idk = bytemuck::pod_read_unaligned(&data[..4]);
-                    // let coords = bytemuck::pod_read_unaligned(&data[4..40]);
-                    let pos = bytemuck::pod_read_unaligned(&data[4..4 + 12]);
-                    let rot = bytemuck::pod_read_unaligned(&data[4 + 12..4 + 24]);
-                    let scale = bytemuck::pod_read_unaligned(&data[4 + 24..40]);
-                    ret.push(Block::Transform(TransformBlock {
-                        name,
-                        idk,
-                        coords: TRS3d { pos, rot, scale },
-                    }))
+    impl TransformBlock<'_> {
+        pub fn into_owned(self) -> TransformBlock<'static> {
+            TransformBlock {
+                name: self.name.into_owned(),
+                rest: Cow::Owned(self.rest.into_owned()),
+                ..self
+            }
+        }
+    }
+
+    impl BoneBlock<'_> {
+        pub fn into_owned(self) -> BoneBlock<'static> {
+            BoneBlock {
+                name: self.name.into_owned(),
+                rest: Cow::Owned(self.rest.into_owned()),
+                ..self
+            }
+        }
+    }
+
+    impl TRS3d {
+        pub fn to_transform(&self, order: EulerOrder) -> Transform {
+            Transform {
+                translation: self.pos,
+                rotation: math::quat_from_euler(self.rot, order),
+                scale: self.scale,
+            }
+        }
+
+        pub fn to_matrix(&self, order: EulerOrder) -> Mat4 {
+            self.to_transform(order).to_matrix()
+        }
+
+        pub fn from_matrix(m: &Mat4, order: EulerOrder) -> Self {
+            let (pos, rotation, scale) = math::decompose(m);
+            Self {
+                pos,
+                rot: math::euler_from_quat(rotation, order),
+                scale,
+            }
+        }
+    }
+
+    impl BoneBlock<'_> {
+        pub fn to_matrix(&self) -> Mat4 {
+            self.matrix
+        }
+
+        pub fn inverse(&self) -> Option<Mat4> {
+            math::inverse(&self.matrix)
+        }
+    }
+
+    impl RawBlock<'_> {
+        pub fn into_owned(self) -> RawBlock<'static> {
+            RawBlock {
+                typ: self.typ,
+                data: Cow::Owned(self.data.into_owned()),
+            }
+        }
+    }
+
+    /// Caps on what one file can make the parser do, for files that can't be trusted.
+    /// Counts are totals over the whole file.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct ParseLimits {
+        pub max_blocks: usize,
+        pub max_vertices: usize,
+        pub max_indices: usize,
+        // bytes, vertex data is borrowed from the input and doesn't count
+        pub max_alloc: usize,
+    }
+
+    // Well above anything the game ships
+    impl Default for ParseLimits {
+        fn default() -> Self {
+            Self {
+                max_blocks: 1 << 16,
+                max_vertices: 1 << 22,
+                max_indices: 1 << 24,
+                max_alloc: 256 << 20,
+            }
+        }
+    }
+
+    impl ParseLimits {
+        pub fn unlimited() -> Self {
+            Self {
+                max_blocks: usize::MAX,
+                max_vertices: usize::MAX,
+                max_indices: usize::MAX,
+                max_alloc: usize::MAX,
+            }
+        }
+    }
+
+    #[derive(Error, Debug)]
+    pub enum ParseError {
+        #[error("block at offset {0} is cut short")]
+        Truncated(usize),
+        #[error("block at offset {0} has an invalid size of {1}")]
+        BadSize(usize, u32),
+        #[error("name in the block at offset {0} isn't terminated")]
+        UnterminatedName(usize),
+        #[error("sizes overflow in the block at offset {0}")]
+        Overflow(usize),
+        // without knowing their size the vertices can't be told apart from what follows them
+        #[error("unknown vertex bits {0:#x}")]
+        UnknownVertexBits(u32),
+        #[error("too many {what} at offset {offset}, the limit is {limit}")]
+        Limit {
+            what: &'static str,
+            offset: usize,
+            limit: usize,
+        },
+    }
+
+    // Bounds checked reads for the block starting at `offset`, and what's been used up so far
+    struct Parser<'l> {
+        limits: &'l ParseLimits,
+        offset: usize,
+        blocks: usize,
+        vertices: usize,
+        indices: usize,
+        alloc: usize,
+    }
+
+    impl Parser<'_> {
+        fn read<T: Le>(&self, r: &mut Reader) -> Result<T, ParseError> {
+            r.read().ok_or(ParseError::Truncated(self.offset))
+        }
+
+        fn bytes<'a>(&self, r: &mut Reader<'a>, len: usize) -> Result<&'a [u8], ParseError> {
+            r.bytes(len).ok_or(ParseError::Truncated(self.offset))
+        }
+
+        fn mul(&self, a: usize, b: usize) -> Result<usize, ParseError> {
+            a.checked_mul(b).ok_or(ParseError::Overflow(self.offset))
+        }
+
+        fn count(
+            offset: usize,
+            used: &mut usize,
+            n: usize,
+            limit: usize,
+            what: &'static str,
+        ) -> Result<(), ParseError> {
+            match used.checked_add(n) {
+                Some(x) if x <= limit => {
+                    *used = x;
+                    Ok(())
                 }
-                11 => {
-                    // Bone
-                    let data = &cursor[..size - 8];
-                    let (name, skip) = read_str(data).unwrap();
-                    let data = &data[skip..];
-                    let idk = bytemuck::pod_read_unaligned(&data[..4]);
-                    let coords = bytemuck::pod_read_unaligned(&data[4..40]);
-                    let data = &data[40..];
-                    let mut c = 0;
-                    let mut matrix = [[0f32; 4]; 4];
-                    for i in 0..4 {
-                        for j in 0..4 {
-                            if i == 3 {
-                                if j == 3 {
-                                    matrix[j][i] = 1f32;
-                                } else {
-                                    matrix[j][i] = 0f32;
-                                }
-                            } else {
-                                matrix[j][i] = bytemuck::pod_read_unaligned(&data[c..c + 4]);
-                                c += 4;
-                            }
-                        }
-                    }
-                    ret.push(Block::Bone(BoneBlock {
-                        name,
-                        idk,
-                        coords,
-                        matrix,
-                    }))
+                _ => Err(ParseError::Limit {
+                    what,
+                    offset,
+                    limit,
+                }),
+            }
+        }
+
+        fn blocks(&mut self, n: usize) -> Result<(), ParseError> {
+            Self::count(
+                self.offset,
+                &mut self.blocks,
+                n,
+                self.limits.max_blocks,
+                "blocks",
+            )?;
+            self.alloc(self.mul(n, std::mem::size_of::<Block>())?)
+        }
+
+        fn vertices(&mut self, n: usize) -> Result<(), ParseError> {
+            Self::count(
+                self.offset,
+                &mut self.vertices,
+                n,
+                self.limits.max_vertices,
+                "vertices",
+            )
+        }
+
+        fn indices(&mut self, n: usize) -> Result<(), ParseError> {
+            Self::count(
+                self.offset,
+                &mut self.indices,
+                n,
+                self.limits.max_indices,
+                "indices",
+            )?;
+            self.alloc(self.mul(n, 2)?)
+        }
+
+        fn alloc(&mut self, bytes: usize) -> Result<(), ParseError> {
+            Self::count(
+                self.offset,
+                &mut self.alloc,
+                bytes,
+                self.limits.max_alloc,
+                "allocated bytes",
+            )
+        }
+
+        // Null terminated and padded to 4 bytes
+        fn read_str<'a>(&self, r: &mut Reader<'a>) -> Result<Name<'a>, ParseError> {
+            let src = r.remaining();
+            let size = src
+                .iter()
+                .position(|&x| x == 0)
+                .ok_or(ParseError::UnterminatedName(self.offset))?;
+            self.bytes(r, 4 * (size / 4) + 4)?;
+            Ok(Name::decode(&src[..size]))
+        }
+
+        fn transform<'a>(&self, data: &'a [u8]) -> Result<TransformBlock<'a>, ParseError> {
+            let mut r = Reader::new(data);
+            Ok(TransformBlock {
+                name: self.read_str(&mut r)?,
+                idk: self.read(&mut r)?,
+                coords: TRS3d {
+                    pos: self.read(&mut r)?,
+                    rot: self.read(&mut r)?,
+                    scale: self.read(&mut r)?,
+                },
+                rest: Cow::Borrowed(r.remaining()),
+            })
+        }
+
+        fn bone<'a>(&self, data: &'a [u8]) -> Result<BoneBlock<'a>, ParseError> {
+            let mut r = Reader::new(data);
+            Ok(BoneBlock {
+                name: self.read_str(&mut r)?,
+                idk: self.read(&mut r)?,
+                coords: self.read(&mut r)?,
+                matrix: math::from_columns_4x3(&self.read(&mut r)?),
+                rest: Cow::Borrowed(r.remaining()),
+            })
+        }
+
+        fn geometry<'a>(&mut self, data: &'a [u8]) -> Result<GeometryBlock<'a>, ParseError> {
+            let mut r = Reader::new(data);
+            let name = self.read_str(&mut r)?;
+            let coords = self.read(&mut r)?;
+            let bool4 = self.read::<u32>(&mut r)? != 0;
+            let vertex_bitmask = self.read::<u32>(&mut r)?;
+            let bool6 = self.read::<u32>(&mut r)? != 0;
+
+            let (idk, vertex_num, vertex_size, vertex_data) = if !bool6 {
+                let vertex_num = self.read::<u32>(&mut r)?;
+                let mask = VertexMask::from_bits(vertex_bitmask);
+                if mask.unknown_bits() != 0 {
+                    return Err(ParseError::UnknownVertexBits(mask.unknown_bits()));
                 }
-                0 => {
-                    // Geometry?
-                    let data = &cursor[..size - 8];
-                    let (name, skip) = read_str(data).unwrap();
-                    let data = &data[skip..];
-                    let coords = bytemuck::pod_read_unaligned(&data[..16]);
-                    let bool4 = bytemuck::pod_read_unaligned::<u32>(&data[16..20]) != 0;
-                    let vertex_bitmask = bytemuck::pod_read_unaligned::<u32>(&data[20..24]);
-                    let bool6 = bytemuck::pod_read_unaligned::<u32>(&data[24..28]) != 0;
-                    let data = &data[28..];
-
-                    let (idk, vertex_num, vertex_size, vertex_data) = if !bool6 {
-                        // todo!();
-                        let vertex_num = bytemuck::pod_read_unaligned::<u32>(&data[..4]);
-                        const SIZES: [usize; 19] = [
-                            12, 12, 12, 12, 16, 16, 4, 4, 4, 4, 16, 8, 8, 8, 8, 8, 8, 8, 8,
-                        ];
-                        let mut vertex_size = 0;
-                        for i in 0..19 {
-                            if (vertex_bitmask >> i) & 1 != 0 {
-                                vertex_size += SIZES[i];
-                            }
-                        }
-                        let vertex_data = &data[4..vertex_num as usize * vertex_size as usize];
-                        let data = &data[4 + vertex_num as usize * vertex_size as usize..];
-
-                        let size = bytemuck::pod_read_unaligned::<u32>(&data[..4]);
-                        let mut data = &data[4..];
-                        let mut ret = Vec::with_capacity(size as usize);
-
-                        for _ in 0..size {
-                            let render_type = bytemuck::pod_read_unaligned::<u32>(&data[..4]);
-                            let w = bytemuck::pod_read_unaligned::<u32>(&data[4..8]);
-                            let words = (0..w as usize)
-                                .map(|x| &data[8 + 2 * x..8 + 2 * x + 2])
-                                .map(|x| bytemuck::pod_read_unaligned(x))
-                                .collect();
-                            data = &data[8 + 2 * w as usize..];
-                            ret.push(GeometryBlockInner {
-                                typ: PTEnum::from(render_type),
-                                words,
-                            });
-                        }
-                        (ret, Some(vertex_num), Some(vertex_size), Some(vertex_data))
-                    } else {
-                        (Vec::new(), None, None, None)
-                    };
-
-                    // let mut idk = Vec::new();
-                    ret.push(Block::Geometry(GeometryBlock {
-                        name,
-                        coords,
-                        bool4,
-                        vertex_bitmask: VertexMask {
-                            mask: vertex_bitmask,
-                        },
-                        bool6,
-                        idk,
-                        vertex_num,
-                        vertex_size,
-                        vertex_data,
-                    }))
+                let vertex_size = mask.vertex_size();
+                self.vertices(vertex_num as usize)?;
+                let len = self.mul(vertex_num as usize, vertex_size)?;
+                let vertex_data = self.bytes(&mut r, len)?;
+
+                let size = self.read::<u32>(&mut r)? as usize;
+                // every group has at least a type and a count
+                if self.mul(size, 8)? > r.remaining().len() {
+                    return Err(ParseError::Truncated(self.offset));
                 }
-                _ => {
-                    // Unknown block!
-                    // TODO: replace
-                    eprintln!("Unknown block {}", typ)
+                self.alloc(self.mul(size, std::mem::size_of::<GeometryBlockInner>())?)?;
+                let mut ret = Vec::with_capacity(size);
+
+                for _ in 0..size {
+                    let render_type = self.read::<u32>(&mut r)?;
+                    let w = self.read::<u32>(&mut r)? as usize;
+                    let words = self.bytes(&mut r, self.mul(w, 2)?)?;
+                    self.indices(w)?;
+                    ret.push(GeometryBlockInner {
+                        typ: PTEnum::from(render_type),
+                        words: le::read_all(words).collect(),
+                    });
                 }
+                (
+                    ret,
+                    Some(vertex_num),
+                    Some(vertex_size),
+                    Some(Cow::Borrowed(vertex_data)),
+                )
+            } else {
+                (Vec::new(), None, None, None)
+            };
+
+            Ok(GeometryBlock {
+                name,
+                coords,
+                bool4,
+                vertex_bitmask: VertexMask {
+                    mask: vertex_bitmask,
+                },
+                bool6,
+                idk,
+                vertex_num,
+                vertex_size,
+                vertex_data,
+                rest: Cow::Borrowed(r.remaining()),
+            })
+        }
+    }
+
+    pub fn read_blocks(src: &[u8]) -> Result<Vec<Block<'_>>, ParseError> {
+        read_blocks_with(src, &ParseLimits::default())
+    }
+
+    pub fn read_blocks_with<'a>(
+        src: &'a [u8],
+        limits: &ParseLimits,
+    ) -> Result<Vec<Block<'a>>, ParseError> {
+        let mut parser = Parser {
+            limits,
+            offset: 0,
+            blocks: 0,
+            vertices: 0,
+            indices: 0,
+            alloc: 0,
+        };
+        let mut ret = Vec::new();
+        let mut cursor = src;
+        while !cursor.is_empty() {
+            parser.offset = src.len() - cursor.len();
+            if cursor.len() < 8 {
+                return Err(ParseError::Truncated(parser.offset));
             }
 
-            cursor = &cursor[size - 8..];
+            let typ = le::read::<u32>(cursor, 0);
+            let size = le::read::<u32>(cursor, 4);
+            cursor = &cursor[8..];
+
+            let data = (size as usize)
+                .checked_sub(8)
+                .and_then(|x| cursor.get(..x))
+                .ok_or(ParseError::BadSize(parser.offset, size))?;
+            parser.blocks(1)?;
+
+            ret.push(match typ {
+                4 => Block::Transform(parser.transform(data)?),
+                11 => Block::Bone(parser.bone(data)?),
+                0 => Block::Geometry(parser.geometry(data)?),
+                // kept as they are so they can be written back
+                _ => Block::Raw(RawBlock {
+                    typ,
+                    data: Cow::Borrowed(data),
+                }),
+            });
+
+            cursor = &cursor[data.len()..];
         }
 
-        ret
+        Ok(ret)
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        const QUAD: &[u8] =
+            include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/quad.hgm"));
+
+        #[test]
+        fn unknown_vertex_bits_are_errors() {
+            let mut blocks = read_blocks(QUAD).unwrap();
+            let Block::Geometry(geometry) = &mut blocks[3] else {
+                panic!("not geometry");
+            };
+            geometry.vertex_bitmask.mask |= 1 << 20;
+            let data = write_blocks(&blocks).unwrap();
+            assert!(matches!(
+                read_blocks(&data),
+                Err(ParseError::UnknownVertexBits(0x100000))
+            ));
+        }
+
+        #[test]
+        fn truncated_input_is_an_error() {
+            // anything but a cut between two blocks
+            let mut ends = Vec::new();
+            let mut at = 0;
+            while at < QUAD.len() {
+                at += le::read::<u32>(QUAD, at + 4) as usize;
+                ends.push(at);
+            }
+            for len in 1..QUAD.len() {
+                let ret = read_blocks(&QUAD[..len]);
+                assert_eq!(ret.is_ok(), ends.contains(&len), "{len} bytes");
+            }
+            assert!(read_blocks(&[]).unwrap().is_empty());
+            // a partial header after the last block
+            let mut data = QUAD.to_vec();
+            data.extend_from_slice(&[4, 0, 0]);
+            assert!(matches!(
+                read_blocks(&data),
+                Err(ParseError::Truncated(420))
+            ));
+        }
+
+        #[test]
+        fn oversized_blocks_are_errors() {
+            for size in [0, 7, 0x1000, u32::MAX] {
+                let mut data = QUAD.to_vec();
+                data[4..8].copy_from_slice(&size.to_le_bytes());
+                assert!(
+                    matches!(read_blocks(&data), Err(ParseError::BadSize(0, x)) if x == size),
+                    "{size}"
+                );
+            }
+        }
+
+        #[test]
+        fn unknown_blocks_are_kept() {
+            let mut data = QUAD.to_vec();
+            for typ in [12u32, 0xdead_beef] {
+                data.extend_from_slice(&typ.to_le_bytes());
+                data.extend_from_slice(&12u32.to_le_bytes());
+                data.extend_from_slice(&typ.to_le_bytes());
+            }
+            let blocks = read_blocks(&data).unwrap();
+            assert_eq!(blocks.len(), 7);
+            assert!(matches!(&blocks[5], Block::Raw(raw) if raw.typ == 12));
+            assert!(
+                matches!(&blocks[6], Block::Raw(raw) if *raw.data == 0xdead_beefu32.to_le_bytes())
+            );
+            assert_eq!(write_blocks(&blocks).unwrap(), data);
+        }
+
+        #[test]
+        fn unknown_primitive_types_stay_raw() {
+            for x in 0..8u32 {
+                let typ = PTEnum::from(x);
+                assert_eq!(u32::from(&typ), x);
+                assert_eq!(typ.is_triangles(), x < 3);
+                assert_eq!(typ.to_d3d().is_some(), x < 3);
+            }
+            assert_eq!(PTEnum::from(3), PTEnum::Unknown(3));
+            assert_eq!(PTEnum::TriangleList.to_d3d(), Some(4));
+            assert_eq!(PTEnum::Unknown(4).primitive_count(12), 0);
+        }
     }
 }
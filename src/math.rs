@@ -0,0 +1,433 @@
+// D3D conventions: row vectors, `v * M`, translation in the last row
+
+pub type Vec3 = [f32; 3];
+pub type Mat4 = [[f32; 4]; 4];
+
+pub const IDENTITY: Mat4 = [
+    [1.0, 0.0, 0.0, 0.0],
+    [0.0, 1.0, 0.0, 0.0],
+    [0.0, 0.0, 1.0, 0.0],
+    [0.0, 0.0, 0.0, 1.0],
+];
+
+/// `a` then `b`.
+pub fn mul(a: &Mat4, b: &Mat4) -> Mat4 {
+    let mut ret = [[0.0; 4]; 4];
+    for (i, row) in ret.iter_mut().enumerate() {
+        for (j, x) in row.iter_mut().enumerate() {
+            *x = (0..4).map(|k| a[i][k] * b[k][j]).sum();
+        }
+    }
+    ret
+}
+
+pub fn transform_point(m: &Mat4, p: Vec3) -> Vec3 {
+    let [x, y, z] = transform_vector(m, p);
+    [x + m[3][0], y + m[3][1], z + m[3][2]]
+}
+
+// Ignores translation
+pub fn transform_vector(m: &Mat4, v: Vec3) -> Vec3 {
+    [0, 1, 2].map(|j| v[0] * m[0][j] + v[1] * m[1][j] + v[2] * m[2][j])
+}
+
+// How bone blocks store their matrix: 3 columns of 4, the last column is implied
+pub fn from_columns_4x3(columns: &[[f32; 4]; 3]) -> Mat4 {
+    let mut ret = IDENTITY;
+    for (j, column) in columns.iter().enumerate() {
+        for (row, x) in ret.iter_mut().zip(column) {
+            row[j] = *x;
+        }
+    }
+    ret
+}
+
+pub fn to_columns_4x3(m: &Mat4) -> [[f32; 4]; 3] {
+    [0, 1, 2].map(|j| [m[0][j], m[1][j], m[2][j], m[3][j]])
+}
+
+pub fn scale(m: &Mat4, s: f32) -> Mat4 {
+    m.map(|row| row.map(|x| x * s))
+}
+
+pub fn add(a: &Mat4, b: &Mat4) -> Mat4 {
+    let mut ret = *a;
+    for (row, other) in ret.iter_mut().zip(b) {
+        for (x, y) in row.iter_mut().zip(other) {
+            *x += y;
+        }
+    }
+    ret
+}
+
+/// Full inverse through cofactors, `None` if the matrix is singular.
+pub fn inverse(m: &Mat4) -> Option<Mat4> {
+    let a = m;
+    let s0 = a[0][0] * a[1][1] - a[1][0] * a[0][1];
+    let s1 = a[0][0] * a[1][2] - a[1][0] * a[0][2];
+    let s2 = a[0][0] * a[1][3] - a[1][0] * a[0][3];
+    let s3 = a[0][1] * a[1][2] - a[1][1] * a[0][2];
+    let s4 = a[0][1] * a[1][3] - a[1][1] * a[0][3];
+    let s5 = a[0][2] * a[1][3] - a[1][2] * a[0][3];
+    let c5 = a[2][2] * a[3][3] - a[3][2] * a[2][3];
+    let c4 = a[2][1] * a[3][3] - a[3][1] * a[2][3];
+    let c3 = a[2][1] * a[3][2] - a[3][1] * a[2][2];
+    let c2 = a[2][0] * a[3][3] - a[3][0] * a[2][3];
+    let c1 = a[2][0] * a[3][2] - a[3][0] * a[2][2];
+    let c0 = a[2][0] * a[3][1] - a[3][0] * a[2][1];
+
+    let det = s0 * c5 - s1 * c4 + s2 * c3 + s3 * c2 - s4 * c1 + s5 * c0;
+    if det.abs() < f32::EPSILON {
+        return None;
+    }
+    let inv = 1.0 / det;
+    Some([
+        [
+            (a[1][1] * c5 - a[1][2] * c4 + a[1][3] * c3) * inv,
+            (-a[0][1] * c5 + a[0][2] * c4 - a[0][3] * c3) * inv,
+            (a[3][1] * s5 - a[3][2] * s4 + a[3][3] * s3) * inv,
+            (-a[2][1] * s5 + a[2][2] * s4 - a[2][3] * s3) * inv,
+        ],
+        [
+            (-a[1][0] * c5 + a[1][2] * c2 - a[1][3] * c1) * inv,
+            (a[0][0] * c5 - a[0][2] * c2 + a[0][3] * c1) * inv,
+            (-a[3][0] * s5 + a[3][2] * s2 - a[3][3] * s1) * inv,
+            (a[2][0] * s5 - a[2][2] * s2 + a[2][3] * s1) * inv,
+        ],
+        [
+            (a[1][0] * c4 - a[1][1] * c2 + a[1][3] * c0) * inv,
+            (-a[0][0] * c4 + a[0][1] * c2 - a[0][3] * c0) * inv,
+            (a[3][0] * s4 - a[3][1] * s2 + a[3][3] * s0) * inv,
+            (-a[2][0] * s4 + a[2][1] * s2 - a[2][3] * s0) * inv,
+        ],
+        [
+            (-a[1][0] * c3 + a[1][1] * c1 - a[1][2] * c0) * inv,
+            (a[0][0] * c3 - a[0][1] * c1 + a[0][2] * c0) * inv,
+            (-a[3][0] * s3 + a[3][1] * s1 - a[3][2] * s0) * inv,
+            (a[2][0] * s3 - a[2][1] * s1 + a[2][2] * s0) * inv,
+        ],
+    ])
+}
+
+// x, y, z, w
+pub type Quat = [f32; 4];
+
+pub const QUAT_IDENTITY: Quat = [0.0, 0.0, 0.0, 1.0];
+
+pub fn lerp3(a: Vec3, b: Vec3, t: f32) -> Vec3 {
+    [0, 1, 2].map(|i| a[i] + (b[i] - a[i]) * t)
+}
+
+pub fn normalize(q: Quat) -> Quat {
+    let len = q.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if len < f32::EPSILON {
+        return QUAT_IDENTITY;
+    }
+    q.map(|x| x / len)
+}
+
+/// Shortest path, falls back to a normalized lerp when the two are nearly the same.
+pub fn slerp(a: Quat, b: Quat, t: f32) -> Quat {
+    let mut dot: f32 = (0..4).map(|i| a[i] * b[i]).sum();
+    let mut b = b;
+    if dot < 0.0 {
+        dot = -dot;
+        b = b.map(|x| -x);
+    }
+    if dot > 0.9995 {
+        return normalize([0, 1, 2, 3].map(|i| a[i] + (b[i] - a[i]) * t));
+    }
+    let theta = dot.acos();
+    let sin = theta.sin();
+    let wa = ((1.0 - t) * theta).sin() / sin;
+    let wb = (t * theta).sin() / sin;
+    [0, 1, 2, 3].map(|i| a[i] * wa + b[i] * wb)
+}
+
+pub fn rotation(q: Quat) -> Mat4 {
+    let [x, y, z, w] = q;
+    [
+        [
+            1.0 - 2.0 * (y * y + z * z),
+            2.0 * (x * y + z * w),
+            2.0 * (x * z - y * w),
+            0.0,
+        ],
+        [
+            2.0 * (x * y - z * w),
+            1.0 - 2.0 * (x * x + z * z),
+            2.0 * (y * z + x * w),
+            0.0,
+        ],
+        [
+            2.0 * (x * z + y * w),
+            2.0 * (y * z - x * w),
+            1.0 - 2.0 * (x * x + y * y),
+            0.0,
+        ],
+        [0.0, 0.0, 0.0, 1.0],
+    ]
+}
+
+/// Scale, then rotate, then translate.
+pub fn compose(translation: Vec3, q: Quat, scale: Vec3) -> Mat4 {
+    let mut ret = rotation(q);
+    for (row, s) in ret.iter_mut().zip(scale) {
+        for x in row.iter_mut().take(3) {
+            *x *= s;
+        }
+    }
+    ret[3] = [translation[0], translation[1], translation[2], 1.0];
+    ret
+}
+
+/// Inverse of `compose`, a mirrored matrix gets a negative x scale.
+pub fn decompose(m: &Mat4) -> (Vec3, Quat, Vec3) {
+    let translation = [m[3][0], m[3][1], m[3][2]];
+    let mut scale = [0, 1, 2].map(|i| (0..3).map(|j| m[i][j] * m[i][j]).sum::<f32>().sqrt());
+    let det = m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+        - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+        + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0]);
+    if det < 0.0 {
+        scale[0] = -scale[0];
+    }
+    let mut r = IDENTITY;
+    for i in 0..3 {
+        for j in 0..3 {
+            r[i][j] = if scale[i] == 0.0 {
+                0.0
+            } else {
+                m[i][j] / scale[i]
+            };
+        }
+    }
+    (translation, quat_from_rotation(&r), scale)
+}
+
+// Column vector terms are the transpose of ours, hence the swapped indices
+pub fn quat_from_rotation(r: &Mat4) -> Quat {
+    let c = |i: usize, j: usize| r[j][i];
+    let trace = c(0, 0) + c(1, 1) + c(2, 2);
+    let q = if trace > 0.0 {
+        let s = (trace + 1.0).sqrt() * 2.0;
+        [
+            (c(2, 1) - c(1, 2)) / s,
+            (c(0, 2) - c(2, 0)) / s,
+            (c(1, 0) - c(0, 1)) / s,
+            0.25 * s,
+        ]
+    } else if c(0, 0) > c(1, 1) && c(0, 0) > c(2, 2) {
+        let s = (1.0 + c(0, 0) - c(1, 1) - c(2, 2)).sqrt() * 2.0;
+        [
+            0.25 * s,
+            (c(0, 1) + c(1, 0)) / s,
+            (c(0, 2) + c(2, 0)) / s,
+            (c(2, 1) - c(1, 2)) / s,
+        ]
+    } else if c(1, 1) > c(2, 2) {
+        let s = (1.0 + c(1, 1) - c(0, 0) - c(2, 2)).sqrt() * 2.0;
+        [
+            (c(0, 1) + c(1, 0)) / s,
+            0.25 * s,
+            (c(1, 2) + c(2, 1)) / s,
+            (c(0, 2) - c(2, 0)) / s,
+        ]
+    } else {
+        let s = (1.0 + c(2, 2) - c(0, 0) - c(1, 1)).sqrt() * 2.0;
+        [
+            (c(0, 2) + c(2, 0)) / s,
+            (c(1, 2) + c(2, 1)) / s,
+            0.25 * s,
+            (c(1, 0) - c(0, 1)) / s,
+        ]
+    };
+    normalize(q)
+}
+
+/// Hamilton product, `b` is applied first.
+pub fn quat_mul(a: Quat, b: Quat) -> Quat {
+    let [ax, ay, az, aw] = a;
+    let [bx, by, bz, bw] = b;
+    [
+        aw * bx + ax * bw + ay * bz - az * by,
+        aw * by - ax * bz + ay * bw + az * bx,
+        aw * bz + ax * by - ay * bx + az * bw,
+        aw * bw - ax * bx - ay * by - az * bz,
+    ]
+}
+
+/// Axes in the order the rotations are applied, `Xyz` turns around X first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EulerOrder {
+    // Assumed for `TRS3d::rot`, in radians. Unconfirmed: no game file has been checked yet, the
+    // inspect_model example reports which order and unit turn a bone's `coords` into its
+    // `matrix`, if any does
+    #[default]
+    Xyz,
+    Xzy,
+    Yxz,
+    Yzx,
+    Zxy,
+    Zyx,
+}
+
+impl EulerOrder {
+    pub const ALL: [Self; 6] = [
+        Self::Xyz,
+        Self::Xzy,
+        Self::Yxz,
+        Self::Yzx,
+        Self::Zxy,
+        Self::Zyx,
+    ];
+
+    fn axes(self) -> [usize; 3] {
+        match self {
+            Self::Xyz => [0, 1, 2],
+            Self::Xzy => [0, 2, 1],
+            Self::Yxz => [1, 0, 2],
+            Self::Yzx => [1, 2, 0],
+            Self::Zxy => [2, 0, 1],
+            Self::Zyx => [2, 1, 0],
+        }
+    }
+
+    // Cyclic orders are the even permutations
+    fn is_even(self) -> bool {
+        matches!(self, Self::Xyz | Self::Yzx | Self::Zxy)
+    }
+}
+
+/// Angles are in radians and indexed by axis, not by order.
+pub fn quat_from_euler(angles: Vec3, order: EulerOrder) -> Quat {
+    let mut ret = QUAT_IDENTITY;
+    for axis in order.axes() {
+        let (sin, cos) = (angles[axis] / 2.0).sin_cos();
+        let mut q = [0.0, 0.0, 0.0, cos];
+        q[axis] = sin;
+        ret = quat_mul(q, ret);
+    }
+    ret
+}
+
+pub fn euler_from_quat(q: Quat, order: EulerOrder) -> Vec3 {
+    let r = rotation(q);
+    let c = |i: usize, j: usize| r[j][i];
+    let [i, j, k] = order.axes();
+    let sign = if order.is_even() { 1.0 } else { -1.0 };
+    let mut ret = [0.0; 3];
+    ret[j] = (-sign * c(k, i)).clamp(-1.0, 1.0).asin();
+    if c(k, i).abs() < 0.9999 {
+        ret[i] = (sign * c(k, j)).atan2(c(k, k));
+        ret[k] = (sign * c(j, i)).atan2(c(i, i));
+    } else {
+        // gimbal lock, put everything on the first axis
+        ret[i] = (-sign * c(j, k)).atan2(c(j, j));
+    }
+    ret
+}
+
+/// Our matrices are glam's transposed, which is just reading rows as columns.
+#[cfg(feature = "glam")]
+pub fn to_glam(m: &Mat4) -> glam::Mat4 {
+    glam::Mat4::from_cols_array_2d(m)
+}
+
+#[cfg(feature = "glam")]
+pub fn from_glam(m: &glam::Mat4) -> Mat4 {
+    m.to_cols_array_2d()
+}
+
+#[cfg(feature = "glam")]
+pub fn quat_to_glam(q: Quat) -> glam::Quat {
+    glam::Quat::from_array(q)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hg::TRS3d;
+
+    const ANGLES: [Vec3; 4] = [
+        [0.3, -0.7, 1.1],
+        [-2.5, 0.4, 0.2],
+        [1.0, 1.2, -3.0],
+        [0.0, 0.0, 0.5],
+    ];
+
+    fn close(a: &[f32], b: &[f32]) -> bool {
+        a.len() == b.len() && a.iter().zip(b).all(|(x, y)| (x - y).abs() < 1e-4)
+    }
+
+    fn close_m(a: &Mat4, b: &Mat4) -> bool {
+        close(a.as_flattened(), b.as_flattened())
+    }
+
+    fn axis_rotation(axis: usize, angle: f32) -> Mat4 {
+        let mut angles = [0.0; 3];
+        angles[axis] = angle;
+        rotation(quat_from_euler(angles, EulerOrder::Xyz))
+    }
+
+    #[test]
+    fn euler_applies_axes_in_order() {
+        // a quarter turn around z takes x to y
+        assert!(close(
+            &transform_point(
+                &axis_rotation(2, std::f32::consts::FRAC_PI_2),
+                [1.0, 0.0, 0.0]
+            ),
+            &[0.0, 1.0, 0.0]
+        ));
+        for order in EulerOrder::ALL {
+            for angles in ANGLES {
+                let expected = order.axes().iter().fold(IDENTITY, |m, &axis| {
+                    mul(&m, &axis_rotation(axis, angles[axis]))
+                });
+                let got = rotation(quat_from_euler(angles, order));
+                assert!(close_m(&got, &expected), "{order:?} {angles:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn euler_roundtrip() {
+        for order in EulerOrder::ALL {
+            for angles in ANGLES {
+                let q = quat_from_euler(angles, order);
+                let back = euler_from_quat(q, order);
+                // angles can come back different, the rotation can't
+                assert!(
+                    close_m(&rotation(quat_from_euler(back, order)), &rotation(q)),
+                    "{order:?} {angles:?} -> {back:?}"
+                );
+            }
+            // inside +-90 degrees on the middle axis they're unique
+            let angles = [0.3, -0.7, 1.1];
+            let back = euler_from_quat(quat_from_euler(angles, order), order);
+            assert!(close(&back, &angles), "{order:?} {back:?}");
+        }
+    }
+
+    #[test]
+    fn trs_matrix_roundtrip() {
+        for order in EulerOrder::ALL {
+            for angles in ANGLES {
+                let trs = TRS3d {
+                    pos: [1.0, -2.0, 3.5],
+                    rot: angles,
+                    scale: [0.5, 2.0, 1.5],
+                };
+                let m = trs.to_matrix(order);
+                let back = TRS3d::from_matrix(&m, order);
+                assert!(close(&back.pos, &trs.pos), "{order:?}");
+                assert!(close(&back.scale, &trs.scale), "{order:?}");
+                assert!(
+                    close_m(&back.to_matrix(order), &m),
+                    "{order:?} {angles:?} -> {:?}",
+                    back.rot
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,80 @@
+use std::fs::File;
+use std::path::Path;
+
+use memmap2::{Mmap, MmapMut, MmapOptions};
+
+use crate::bin::{decrypt_in_place, DecryptError};
+
+// Mapped files must not be changed by anyone else while the mapping is alive, that's
+// what makes these unsafe. Game data sitting in an install folder is fine.
+
+/// Whole file, read only. Derefs to `[u8]`, so `hg::read_blocks` and friends take it as is.
+pub fn map<P: AsRef<Path>>(path: P) -> std::io::Result<Mmap> {
+    let file = File::open(path)?;
+    // SAFETY: see above
+    unsafe { Mmap::map(&file) }
+}
+
+/// `bin::decrypt_file` without reading the file first. The mapping is private, decrypting
+/// copies pages on write and the file itself is never touched.
+pub fn decrypt_file<P: AsRef<Path>>(path: P, key: u32) -> Result<MmapMut, DecryptError> {
+    decrypt_file_range(path, key, 0, None)
+}
+
+/// Only `len` bytes (the rest of the file if None) from `offset`, so the pages of
+/// everything else are never copied.
+pub fn decrypt_file_range<P: AsRef<Path>>(
+    path: P,
+    key: u32,
+    offset: usize,
+    len: Option<usize>,
+) -> Result<MmapMut, DecryptError> {
+    let file = File::open(path)?;
+    let size = file.metadata()?.len() as usize;
+    let len = len.unwrap_or(size.saturating_sub(offset));
+    if offset.checked_add(len).is_none_or(|end| end > size) {
+        return Err(DecryptError::OutOfBounds { offset, len, size });
+    }
+    let mut options = MmapOptions::new();
+    options.offset(offset as u64).len(len);
+    // SAFETY: see above
+    let mut ret = unsafe { options.map_copy(&file)? };
+    decrypt_in_place(&mut ret, key, offset);
+    Ok(ret)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bin::decrypt;
+
+    const KEY: u32 = 0x1234_5678;
+
+    #[test]
+    fn ranges_keep_the_key_phase() {
+        let plain: Vec<u8> = (0..64u8).map(|x| x.wrapping_mul(37)).collect();
+        let path = std::env::temp_dir().join(format!("mmap-range-{}", std::process::id()));
+        std::fs::write(&path, decrypt(&plain, KEY)).unwrap();
+
+        assert_eq!(*decrypt_file(&path, KEY).unwrap(), *plain);
+        for offset in [1, 2, 3, 5, 6, 7, 33] {
+            for len in [1, 3, 4, 9, 64 - offset] {
+                let range = decrypt_file_range(&path, KEY, offset, Some(len)).unwrap();
+                assert_eq!(*range, plain[offset..offset + len], "{offset} {len}");
+            }
+            let rest = decrypt_file_range(&path, KEY, offset, None).unwrap();
+            assert_eq!(*rest, plain[offset..]);
+        }
+
+        for (offset, len) in [(60, Some(8)), (65, None), (1, Some(usize::MAX))] {
+            assert!(
+                matches!(
+                    decrypt_file_range(&path, KEY, offset, len),
+                    Err(DecryptError::OutOfBounds { size: 64, .. })
+                ),
+                "{offset} {len:?}"
+            );
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+}
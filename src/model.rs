@@ -0,0 +1,414 @@
+use thiserror::Error;
+
+use crate::hg::{
+    read_blocks, Block, BoneBlock, GeometryBlock, ParseError, RawBlock, TransformBlock,
+};
+
+const SHADER: u32 = 1;
+const TEXTURE: u32 = 3;
+
+#[derive(Debug, Clone)]
+pub struct Mesh<'a> {
+    pub geometry: GeometryBlock<'a>,
+    // guess: the last shader block before the geometry
+    pub material: Option<usize>,
+}
+
+// Parents live in `Model::hierarchy`, files don't give them to us yet
+#[derive(Debug, Clone)]
+pub struct Node<'a> {
+    pub transform: TransformBlock<'a>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Bone<'a> {
+    pub block: BoneBlock<'a>,
+}
+
+impl Bone<'_> {
+    pub fn bind_matrix(&self) -> [[f32; 4]; 4] {
+        self.block.matrix
+    }
+}
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum HierarchyError {
+    #[error("{what}: {got} entries for {expected}")]
+    Length {
+        what: &'static str,
+        got: usize,
+        expected: usize,
+    },
+    #[error("{what} {index}: parent {parent} out of range")]
+    BadParent {
+        what: &'static str,
+        index: usize,
+        parent: usize,
+    },
+    #[error("{what} {index} is its own ancestor")]
+    Cycle { what: &'static str, index: usize },
+}
+
+/// How nodes, bones and meshes hang off each other.
+///
+/// The game keeps this in the hierarchy block (type 8) which isn't decoded yet, so
+/// `Model::read` never fills it in. Anything that needs it (world space matrices, posing from
+/// local transforms) says so by returning nothing until someone calls `Model::set_hierarchy`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Hierarchy {
+    // one per node, None for roots
+    pub node_parents: Vec<Option<usize>>,
+    // one per bone, None for roots
+    pub bone_parents: Vec<Option<usize>>,
+    // node every mesh is placed by, None for meshes already in model space
+    pub mesh_nodes: Vec<Option<usize>>,
+}
+
+fn check_parents(what: &'static str, parents: &[Option<usize>]) -> Result<(), HierarchyError> {
+    for (index, parent) in parents.iter().enumerate() {
+        if let Some(parent) = *parent {
+            if parent >= parents.len() {
+                return Err(HierarchyError::BadParent {
+                    what,
+                    index,
+                    parent,
+                });
+            }
+        }
+        let mut current = *parent;
+        for _ in 0..parents.len() {
+            match current {
+                Some(x) if x == index => return Err(HierarchyError::Cycle { what, index }),
+                Some(x) => current = parents[x],
+                None => break,
+            }
+        }
+        if current.is_some() {
+            return Err(HierarchyError::Cycle { what, index });
+        }
+    }
+    Ok(())
+}
+
+impl Hierarchy {
+    /// Everything a root: nodes and bones have no parents, meshes are in model space.
+    pub fn flat(model: &Model) -> Self {
+        Self {
+            node_parents: vec![None; model.nodes.len()],
+            bone_parents: vec![None; model.skeleton.len()],
+            mesh_nodes: vec![None; model.meshes.len()],
+        }
+    }
+
+    /// Parent indices as some field of the blocks might store them, `u32::MAX` for roots.
+    ///
+    /// Meant for testing guesses like "`BoneBlock::idk` is the parent" against real files (see
+    /// the inspect_model example), nothing here is known to store parents this way.
+    pub fn parents_from_indices(
+        what: &'static str,
+        indices: impl IntoIterator<Item = u32>,
+    ) -> Result<Vec<Option<usize>>, HierarchyError> {
+        let ret: Vec<Option<usize>> = indices
+            .into_iter()
+            .map(|x| (x != u32::MAX).then_some(x as usize))
+            .collect();
+        check_parents(what, &ret)?;
+        Ok(ret)
+    }
+
+    /// Same lengths as the model, parents in range and no loops.
+    pub fn validate(&self, model: &Model) -> Result<(), HierarchyError> {
+        let lengths = [
+            ("nodes", self.node_parents.len(), model.nodes.len()),
+            ("bones", self.bone_parents.len(), model.skeleton.len()),
+            ("meshes", self.mesh_nodes.len(), model.meshes.len()),
+        ];
+        for (what, got, expected) in lengths {
+            if got != expected {
+                return Err(HierarchyError::Length {
+                    what,
+                    got,
+                    expected,
+                });
+            }
+        }
+        check_parents("node", &self.node_parents)?;
+        check_parents("bone", &self.bone_parents)?;
+        for (index, node) in self.mesh_nodes.iter().enumerate() {
+            if let Some(parent) = *node {
+                if parent >= model.nodes.len() {
+                    return Err(HierarchyError::BadParent {
+                        what: "mesh",
+                        index,
+                        parent,
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// Where every block came from, so the file can be written back in order
+#[derive(Debug, Clone, Copy)]
+enum Slot {
+    Mesh(usize),
+    Node(usize),
+    Bone(usize),
+    Material(usize),
+    Texture(usize),
+    Other(usize),
+}
+
+/// Blocks of one hgm sorted into what they are.
+#[derive(Debug, Clone, Default)]
+pub struct Model<'a> {
+    pub meshes: Vec<Mesh<'a>>,
+    pub nodes: Vec<Node<'a>>,
+    pub skeleton: Vec<Bone<'a>>,
+    // shader blocks, kept raw for now
+    pub materials: Vec<RawBlock<'a>>,
+    pub textures: Vec<RawBlock<'a>>,
+    // hierarchy, animation and everything else we can't make sense of yet
+    pub other: Vec<Block<'a>>,
+
+    // always None from `read`, see `Hierarchy`
+    hierarchy: Option<Hierarchy>,
+    order: Vec<Slot>,
+}
+
+impl<'a> Model<'a> {
+    pub fn read(src: &'a [u8]) -> Result<Self, ParseError> {
+        Ok(Self::from_blocks(read_blocks(src)?))
+    }
+
+    pub fn from_blocks(blocks: Vec<Block<'a>>) -> Self {
+        let mut ret = Self::default();
+        for block in blocks {
+            let slot = match block {
+                Block::Geometry(geometry) => {
+                    ret.meshes.push(Mesh {
+                        geometry,
+                        material: ret.materials.len().checked_sub(1),
+                    });
+                    Slot::Mesh(ret.meshes.len() - 1)
+                }
+                Block::Transform(transform) => {
+                    ret.nodes.push(Node { transform });
+                    Slot::Node(ret.nodes.len() - 1)
+                }
+                Block::Bone(block) => {
+                    ret.skeleton.push(Bone { block });
+                    Slot::Bone(ret.skeleton.len() - 1)
+                }
+                Block::Raw(raw) if raw.typ == SHADER => {
+                    ret.materials.push(raw);
+                    Slot::Material(ret.materials.len() - 1)
+                }
+                Block::Raw(raw) if raw.typ == TEXTURE => {
+                    ret.textures.push(raw);
+                    Slot::Texture(ret.textures.len() - 1)
+                }
+                block => {
+                    ret.other.push(block);
+                    Slot::Other(ret.other.len() - 1)
+                }
+            };
+            ret.order.push(slot);
+        }
+        ret
+    }
+
+    /// Blocks in the order they were read, anything added since goes at the end.
+    pub fn into_blocks(self) -> Vec<Block<'a>> {
+        fn take<T>(x: Vec<T>) -> Vec<Option<T>> {
+            x.into_iter().map(Some).collect()
+        }
+        let mut meshes = take(self.meshes);
+        let mut nodes = take(self.nodes);
+        let mut skeleton = take(self.skeleton);
+        let mut materials = take(self.materials);
+        let mut textures = take(self.textures);
+        let mut other = take(self.other);
+
+        let mut ret = Vec::new();
+        for slot in self.order {
+            let block = match slot {
+                Slot::Mesh(i) => meshes
+                    .get_mut(i)
+                    .and_then(Option::take)
+                    .map(|x| Block::Geometry(x.geometry)),
+                Slot::Node(i) => nodes
+                    .get_mut(i)
+                    .and_then(Option::take)
+                    .map(|x| Block::Transform(x.transform)),
+                Slot::Bone(i) => skeleton
+                    .get_mut(i)
+                    .and_then(Option::take)
+                    .map(|x| Block::Bone(x.block)),
+                Slot::Material(i) => materials.get_mut(i).and_then(Option::take).map(Block::Raw),
+                Slot::Texture(i) => textures.get_mut(i).and_then(Option::take).map(Block::Raw),
+                Slot::Other(i) => other.get_mut(i).and_then(Option::take),
+            };
+            ret.extend(block);
+        }
+        ret.extend(materials.into_iter().flatten().map(Block::Raw));
+        ret.extend(textures.into_iter().flatten().map(Block::Raw));
+        ret.extend(
+            nodes
+                .into_iter()
+                .flatten()
+                .map(|x| Block::Transform(x.transform)),
+        );
+        ret.extend(skeleton.into_iter().flatten().map(|x| Block::Bone(x.block)));
+        ret.extend(
+            meshes
+                .into_iter()
+                .flatten()
+                .map(|x| Block::Geometry(x.geometry)),
+        );
+        ret.extend(other.into_iter().flatten());
+        ret
+    }
+
+    pub fn into_owned(self) -> Model<'static> {
+        Model {
+            meshes: self
+                .meshes
+                .into_iter()
+                .map(|x| Mesh {
+                    geometry: x.geometry.into_owned(),
+                    material: x.material,
+                })
+                .collect(),
+            nodes: self
+                .nodes
+                .into_iter()
+                .map(|x| Node {
+                    transform: x.transform.into_owned(),
+                })
+                .collect(),
+            skeleton: self
+                .skeleton
+                .into_iter()
+                .map(|x| Bone {
+                    block: x.block.into_owned(),
+                })
+                .collect(),
+            materials: self
+                .materials
+                .into_iter()
+                .map(RawBlock::into_owned)
+                .collect(),
+            textures: self
+                .textures
+                .into_iter()
+                .map(RawBlock::into_owned)
+                .collect(),
+            other: self.other.into_iter().map(Block::into_owned).collect(),
+            hierarchy: self.hierarchy,
+            order: self.order,
+        }
+    }
+
+    pub fn hierarchy(&self) -> Option<&Hierarchy> {
+        self.hierarchy.as_ref()
+    }
+
+    /// For hierarchies that come from somewhere else than the file, checked against the model.
+    /// Adding or removing nodes, bones or meshes afterwards is on the caller.
+    pub fn set_hierarchy(&mut self, hierarchy: Option<Hierarchy>) -> Result<(), HierarchyError> {
+        if let Some(x) = &hierarchy {
+            x.validate(self)?;
+        }
+        self.hierarchy = hierarchy;
+        Ok(())
+    }
+
+    pub fn mesh_index(&self, name: &str) -> Option<usize> {
+        self.meshes.iter().position(|x| x.geometry.name == name)
+    }
+
+    pub fn mesh(&self, name: &str) -> Option<&Mesh<'a>> {
+        self.mesh_index(name).map(|i| &self.meshes[i])
+    }
+
+    pub fn node_index(&self, name: &str) -> Option<usize> {
+        self.nodes.iter().position(|x| x.transform.name == name)
+    }
+
+    pub fn node(&self, name: &str) -> Option<&Node<'a>> {
+        self.node_index(name).map(|i| &self.nodes[i])
+    }
+
+    pub fn bone_index(&self, name: &str) -> Option<usize> {
+        self.skeleton.iter().position(|x| x.block.name == name)
+    }
+
+    pub fn bone(&self, name: &str) -> Option<&Bone<'a>> {
+        self.bone_index(name).map(|i| &self.skeleton[i])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const QUAD: &[u8] = include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/quad.hgm"));
+
+    #[test]
+    fn sorts_blocks() {
+        let model = Model::read(QUAD).unwrap();
+        assert_eq!(model.nodes.len(), 1);
+        assert_eq!(model.skeleton.len(), 1);
+        assert_eq!(model.materials.len(), 1);
+        assert_eq!(model.meshes.len(), 2);
+        assert_eq!(model.meshes[0].material, Some(0));
+        assert_eq!(model.mesh_index("empty"), Some(1));
+        assert!(model.bone("bone_01").is_some());
+        // nothing in the file says how these connect
+        assert!(model.hierarchy().is_none());
+    }
+
+    #[test]
+    fn hierarchy_is_checked() {
+        let mut model = Model::read(QUAD).unwrap();
+        let mut hierarchy = Hierarchy::flat(&model);
+        model.set_hierarchy(Some(hierarchy.clone())).unwrap();
+        assert_eq!(model.hierarchy(), Some(&hierarchy));
+
+        hierarchy.mesh_nodes[0] = Some(1);
+        assert_eq!(
+            model.set_hierarchy(Some(hierarchy.clone())),
+            Err(HierarchyError::BadParent {
+                what: "mesh",
+                index: 0,
+                parent: 1
+            })
+        );
+        hierarchy.mesh_nodes.pop();
+        assert!(matches!(
+            model.set_hierarchy(Some(hierarchy)),
+            Err(HierarchyError::Length { what: "meshes", .. })
+        ));
+    }
+
+    #[test]
+    fn parents_from_indices() {
+        let parents = Hierarchy::parents_from_indices("bone", [u32::MAX, 0, 1, 0]).unwrap();
+        assert_eq!(parents, [None, Some(0), Some(1), Some(0)]);
+        assert_eq!(
+            Hierarchy::parents_from_indices("bone", [u32::MAX, 4]),
+            Err(HierarchyError::BadParent {
+                what: "bone",
+                index: 1,
+                parent: 4
+            })
+        );
+        assert!(matches!(
+            Hierarchy::parents_from_indices("node", [u32::MAX, 2, 3, 1]),
+            Err(HierarchyError::Cycle { what: "node", .. })
+        ));
+        assert!(Hierarchy::parents_from_indices("node", [0]).is_err());
+    }
+}
@@ -0,0 +1,484 @@
+use std::borrow::Cow;
+
+use encoding_rs::SHIFT_JIS;
+
+// The game is Japanese, names that aren't plain ASCII are CP932 (encoding_rs' Shift_JIS is CP932)
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum NameEncoding {
+    // ASCII included
+    Utf8,
+    ShiftJis,
+    // Neither worked, bad bytes were replaced
+    Lossy,
+}
+
+/// A name as stored in the game files, along with its decoded text.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Name<'a> {
+    raw: Cow<'a, [u8]>,
+    text: Cow<'a, str>,
+    encoding: NameEncoding,
+}
+
+impl<'a> Name<'a> {
+    pub fn decode(raw: &'a [u8]) -> Self {
+        if let Ok(text) = std::str::from_utf8(raw) {
+            return Self {
+                raw: Cow::Borrowed(raw),
+                text: Cow::Borrowed(text),
+                encoding: NameEncoding::Utf8,
+            };
+        }
+        let (text, encoding) = decode_sjis(raw);
+        Self {
+            raw: Cow::Borrowed(raw),
+            text: Cow::Owned(text),
+            encoding,
+        }
+    }
+
+    pub fn raw(&self) -> &[u8] {
+        &self.raw
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.text
+    }
+
+    pub fn encoding(&self) -> NameEncoding {
+        self.encoding
+    }
+
+    pub fn into_owned(self) -> Name<'static> {
+        Name {
+            raw: Cow::Owned(self.raw.into_owned()),
+            text: Cow::Owned(self.text.into_owned()),
+            encoding: self.encoding,
+        }
+    }
+
+    /// Safe to use as a file name anywhere, see `safe_file_name`.
+    pub fn file_name(&self) -> String {
+        safe_file_name(&self.text)
+    }
+}
+
+impl Name<'static> {
+    pub fn decode_owned(raw: Vec<u8>) -> Self {
+        match String::from_utf8(raw) {
+            Ok(text) => Self {
+                raw: Cow::Owned(text.as_bytes().to_vec()),
+                text: Cow::Owned(text),
+                encoding: NameEncoding::Utf8,
+            },
+            Err(e) => {
+                let raw = e.into_bytes();
+                let (text, encoding) = decode_sjis(&raw);
+                Self {
+                    raw: Cow::Owned(raw),
+                    text: Cow::Owned(text),
+                    encoding,
+                }
+            }
+        }
+    }
+
+    /// New name for writing back into the game, stored as Shift-JIS when it can be.
+    pub fn new(text: impl Into<String>) -> Self {
+        let text = text.into();
+        let (raw, _, had_errors) = SHIFT_JIS.encode(&text);
+        let (raw, encoding) = if text.is_ascii() || had_errors {
+            (text.as_bytes().to_vec(), NameEncoding::Utf8)
+        } else {
+            (raw.into_owned(), NameEncoding::ShiftJis)
+        };
+        Self {
+            raw: Cow::Owned(raw),
+            text: Cow::Owned(text),
+            encoding,
+        }
+    }
+}
+
+fn decode_sjis(raw: &[u8]) -> (String, NameEncoding) {
+    match SHIFT_JIS.decode_without_bom_handling_and_without_replacement(raw) {
+        Some(text) => (text.into_owned(), NameEncoding::ShiftJis),
+        None => (
+            SHIFT_JIS.decode_without_bom_handling(raw).0.into_owned(),
+            NameEncoding::Lossy,
+        ),
+    }
+}
+
+impl From<String> for Name<'static> {
+    fn from(text: String) -> Self {
+        Self::new(text)
+    }
+}
+
+impl From<&str> for Name<'static> {
+    fn from(text: &str) -> Self {
+        Self::new(text)
+    }
+}
+
+impl std::ops::Deref for Name<'_> {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.text
+    }
+}
+
+impl AsRef<str> for Name<'_> {
+    fn as_ref(&self) -> &str {
+        &self.text
+    }
+}
+
+impl PartialEq<str> for Name<'_> {
+    fn eq(&self, other: &str) -> bool {
+        self.text == other
+    }
+}
+
+impl PartialEq<&str> for Name<'_> {
+    fn eq(&self, other: &&str) -> bool {
+        self.text == *other
+    }
+}
+
+impl std::fmt::Display for Name<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+// Only the text, the raw bytes are rarely interesting
+impl std::fmt::Debug for Name<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self.text)
+    }
+}
+
+// Leaves room for an extension and a rename suffix under the usual 255 byte limit
+const MAX_FILE_NAME: usize = 200;
+
+// Windows won't create these, whatever the extension
+const RESERVED: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// `transliterate`d, then made into a single plain path component: never empty, no leading or
+/// trailing dots (so no `.` or `..`), no reserved Windows device names and not too long.
+pub fn safe_file_name(name: &str) -> String {
+    let mut ret = transliterate(name);
+    ret.truncate(MAX_FILE_NAME);
+    if ret.starts_with('.') {
+        ret.replace_range(..1, "_");
+    }
+    if ret.ends_with('.') {
+        ret.pop();
+        ret.push('_');
+    }
+    let stem = ret.split('.').next().unwrap_or_default();
+    if ret.is_empty() || RESERVED.iter().any(|x| stem.eq_ignore_ascii_case(x)) {
+        ret.insert(0, '_');
+    }
+    ret
+}
+
+// Hepburn-ish, hiragana from U+3041 to U+3094. Katakana is the same 0x60 higher.
+// Small tsu is handled separately
+const KANA: [&str; 84] = [
+    "a", "a", "i", "i", "u", "u", "e", "e", "o", "o", // ぁ - お
+    "ka", "ga", "ki", "gi", "ku", "gu", "ke", "ge", "ko", "go", // か - ご
+    "sa", "za", "shi", "ji", "su", "zu", "se", "ze", "so", "zo", // さ - ぞ
+    "ta", "da", "chi", "ji", "", "tsu", "zu", "te", "de", "to", "do", // た - ど
+    "na", "ni", "nu", "ne", "no", // な - の
+    "ha", "ba", "pa", "hi", "bi", "pi", "fu", "bu", "pu", "he", "be", "pe", "ho", "bo", "po", "ma",
+    "mi", "mu", "me", "mo", // ま - も
+    "ya", "ya", "yu", "yu", "yo", "yo", // ゃ - よ
+    "ra", "ri", "ru", "re", "ro", // ら - ろ
+    "wa", "wa", "wi", "we", "wo", "n", "vu", // ゎ - ゔ
+];
+
+const SMALL_TSU: u32 = 0x3063;
+
+// Half width katakana U+FF66 to U+FF9D as full width
+const HALF_WIDTH: &str =
+    "ヲァィゥェォャュョッーアイウエオカキクケコサシスセソタチツテトナニヌネノハヒフヘホマミムメモヤユヨラリルレロワン";
+
+fn hiragana(c: char) -> Option<u32> {
+    match c as u32 {
+        x @ 0x3041..=0x3094 => Some(x),
+        x @ 0x30A1..=0x30F4 => Some(x - 0x60),
+        _ => None,
+    }
+}
+
+fn is_small_vowel(c: u32) -> bool {
+    matches!(c, 0x3041 | 0x3043 | 0x3045 | 0x3047 | 0x3049)
+}
+
+fn is_small_y(c: u32) -> bool {
+    matches!(c, 0x3083 | 0x3085 | 0x3087)
+}
+
+// Half width kana with its (han)dakuten folded in, as full width
+fn full_width_kana(src: &str) -> String {
+    let mut ret = String::with_capacity(src.len());
+    for c in src.chars() {
+        let composed = match (c, ret.chars().last()) {
+            // ウ -> ヴ
+            ('\u{FF9E}', Some('ウ')) => Some('ヴ'),
+            // カ to ト and ハ to ホ are followed by their voiced forms
+            ('\u{FF9E}', Some(p @ ('カ'..='ト' | 'ハ'..='ホ'))) => char::from_u32(p as u32 + 1),
+            ('\u{FF9F}', Some(p @ 'ハ'..='ホ')) => char::from_u32(p as u32 + 2),
+            _ => None,
+        };
+        if let Some(composed) = composed {
+            ret.pop();
+            ret.push(composed);
+            continue;
+        }
+        match c as u32 {
+            x @ 0xFF66..=0xFF9D => ret.extend(HALF_WIDTH.chars().nth((x - 0xFF66) as usize)),
+            _ => ret.push(c),
+        }
+    }
+    ret
+}
+
+/// Something every file system is happy with: ASCII letters, digits and `-_.`.
+/// Kana becomes romaji, full width ASCII becomes ASCII, anything else is its code point in hex.
+pub fn transliterate(name: &str) -> String {
+    let mut ret = String::with_capacity(name.len());
+    // after a small tsu
+    let mut double = false;
+    for c in full_width_kana(name).chars() {
+        if let Some(kana) = hiragana(c) {
+            if kana == SMALL_TSU {
+                double = true;
+                continue;
+            }
+            let romaji = KANA[(kana - 0x3041) as usize];
+            let consonant = ret
+                .chars()
+                .rev()
+                .nth(1)
+                .is_some_and(|x| x.is_ascii_alphabetic() && !"aiueo".contains(x));
+            if is_small_y(kana) && consonant && ret.ends_with('i') {
+                // き + ゃ -> kya, し + ゃ -> sha
+                ret.pop();
+                if ret.ends_with("sh") || ret.ends_with("ch") || ret.ends_with('j') {
+                    ret.push_str(&romaji[1..]);
+                } else {
+                    ret.push_str(romaji);
+                }
+                continue;
+            }
+            if is_small_vowel(kana) && consonant && ret.ends_with(['a', 'i', 'u', 'e', 'o']) {
+                // フ + ァ -> fa, テ + ィ -> ti
+                ret.pop();
+                ret.push_str(romaji);
+                continue;
+            }
+            if double {
+                double = false;
+                match romaji.chars().next() {
+                    Some('c') => ret.push('t'),
+                    Some(x) if !"aiueon".contains(x) => ret.push(x),
+                    _ => ret.push_str("tsu"),
+                }
+            }
+            ret.push_str(romaji);
+            continue;
+        }
+        if double {
+            double = false;
+            ret.push_str("tsu");
+        }
+        match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '_' | '.' => ret.push(c),
+            // full width ASCII
+            '\u{FF01}'..='\u{FF5E}' => {
+                let ascii = char::from_u32(c as u32 - 0xFEE0).unwrap_or('_');
+                if ascii.is_ascii_alphanumeric() || "-_.".contains(ascii) {
+                    ret.push(ascii);
+                } else {
+                    ret.push('_');
+                }
+            }
+            // long vowel mark
+            'ー' => ret.push('-'),
+            _ if c.is_ascii() || c.is_whitespace() || c == '・' => ret.push('_'),
+            _ => ret.push_str(&format!("u{:04X}", c as u32)),
+        }
+    }
+    if double {
+        ret.push_str("tsu");
+    }
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::{Component, Path};
+
+    // One plain component, nothing a file system would read as a separator or a device
+    fn assert_safe(name: &str) {
+        let ret = safe_file_name(name);
+        let mut components = Path::new(&ret).components();
+        assert!(
+            matches!(
+                (components.next(), components.next()),
+                (Some(Component::Normal(_)), None)
+            ),
+            "{name:?} -> {ret:?}"
+        );
+        assert!(!ret.contains(['/', '\\', ':']), "{name:?} -> {ret:?}");
+        assert!(!ret.starts_with('.') && !ret.ends_with('.'), "{ret:?}");
+        assert!(ret.len() <= MAX_FILE_NAME, "{ret:?}");
+    }
+
+    #[test]
+    fn paths_become_one_component() {
+        for name in [
+            "..",
+            ".",
+            "../../etc/passwd",
+            "a/b",
+            "a\\b",
+            "..\\..\\boot.ini",
+            "C:",
+            "C:\\Windows",
+            "/etc/passwd",
+            "\\\\server\\share",
+        ] {
+            assert_safe(name);
+        }
+        assert_eq!(safe_file_name("../x"), "_._x");
+        assert_eq!(safe_file_name("a/b\\c"), "a_b_c");
+        assert_eq!(safe_file_name("C:\\x"), "C__x");
+        assert_eq!(safe_file_name("/x"), "_x");
+    }
+
+    #[test]
+    fn reserved_names_are_prefixed() {
+        for name in ["CON", "con", "NUL", "COM1", "lpt9", "nul.txt", "CON.tar.gz"] {
+            let ret = safe_file_name(name);
+            assert_eq!(ret, format!("_{name}"));
+            assert_safe(name);
+        }
+        // only the whole stem counts
+        assert_eq!(safe_file_name("CONSOLE"), "CONSOLE");
+        assert_eq!(safe_file_name("COM10"), "COM10");
+    }
+
+    #[test]
+    fn degenerate_names() {
+        assert_eq!(safe_file_name(""), "_");
+        assert_eq!(safe_file_name("..."), "_._");
+        assert_eq!(safe_file_name("   "), "___");
+        assert_eq!(safe_file_name(" . "), "_._");
+        for name in ["", ".", "...", "   ", " . "] {
+            assert_safe(name);
+        }
+        let long = "a".repeat(1000);
+        assert_eq!(safe_file_name(&long).len(), MAX_FILE_NAME);
+        // cut right before a dot
+        let dotted = format!("{}.x", "a".repeat(MAX_FILE_NAME - 1));
+        assert_safe(&dotted);
+        // kana grows when transliterated, the limit is on the result
+        assert_safe(&"し".repeat(MAX_FILE_NAME));
+    }
+
+    // テスト in CP932
+    const TESUTO: &[u8] = &[0x83, 0x65, 0x83, 0x58, 0x83, 0x67];
+
+    #[test]
+    fn decodes_shift_jis() {
+        let name = Name::decode(TESUTO);
+        assert_eq!(name, "テスト");
+        assert_eq!(name.encoding(), NameEncoding::ShiftJis);
+        assert_eq!(name.raw(), TESUTO);
+        let owned = Name::decode_owned(TESUTO.to_vec());
+        assert_eq!(owned, name);
+        assert_eq!(owned.raw(), TESUTO);
+        assert_eq!(name.clone().into_owned().raw(), TESUTO);
+
+        // written back the way it was read
+        let new = Name::new("テスト");
+        assert_eq!(new.encoding(), NameEncoding::ShiftJis);
+        assert_eq!(new.raw(), TESUTO);
+    }
+
+    #[test]
+    fn bad_bytes_are_lossy() {
+        // a lead byte with nothing after it
+        let raw = [b'a', 0x83];
+        for name in [Name::decode(&raw), Name::decode_owned(raw.to_vec())] {
+            assert_eq!(name.encoding(), NameEncoding::Lossy);
+            assert_eq!(name, "a\u{FFFD}");
+            assert_eq!(name.raw(), raw);
+        }
+    }
+
+    #[test]
+    fn detects_utf8() {
+        for raw in ["plain.hgm".as_bytes(), "テスト".as_bytes()] {
+            for name in [Name::decode(raw), Name::decode_owned(raw.to_vec())] {
+                assert_eq!(name.encoding(), NameEncoding::Utf8);
+                assert_eq!(name.raw(), raw);
+            }
+        }
+        assert_eq!(Name::decode("テスト".as_bytes()), "テスト");
+        // ASCII stays ASCII, and text Shift-JIS can't hold stays UTF-8
+        assert_eq!(Name::new("abc").encoding(), NameEncoding::Utf8);
+        let emoji = Name::new("a\u{1F600}");
+        assert_eq!(emoji.encoding(), NameEncoding::Utf8);
+        assert_eq!(emoji.raw(), "a\u{1F600}".as_bytes());
+    }
+
+    #[test]
+    fn transliterates_kana() {
+        for (kana, romaji) in [
+            ("さくら", "sakura"),
+            ("テスト", "tesuto"),
+            // small tsu doubles what follows, tsu on its own
+            ("がっこう", "gakkou"),
+            ("マッチ", "matchi"),
+            ("あっ", "atsu"),
+            ("っa", "tsua"),
+            // small ya, yu, yo
+            ("きょう", "kyou"),
+            ("しゃしん", "shashin"),
+            ("ちゅう", "chuu"),
+            ("じょ", "jo"),
+            ("まっちゃ", "matcha"),
+            ("ゃ", "ya"),
+            // small vowels
+            ("ファイル", "fairu"),
+            // long vowel mark
+            ("ラーメン", "ra-men"),
+            // half width, dakuten and handakuten folded in
+            ("ﾃｽﾄ", "tesuto"),
+            ("ｶﾞｯｺｳ", "gakkou"),
+            ("ﾊﾟﾝ", "pan"),
+            ("ﾊﾞｲｸ", "baiku"),
+            ("ｳﾞ", "vu"),
+            ("ｰ", "-"),
+            // full width ASCII and everything else
+            ("ＡＢＣ１", "ABC1"),
+            ("a b・c", "a_b_c"),
+            ("漢字", "u6F22u5B57"),
+        ] {
+            assert_eq!(transliterate(kana), romaji, "{kana}");
+        }
+    }
+}
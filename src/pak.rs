@@ -0,0 +1,394 @@
+use std::collections::HashSet;
+use std::path::{Component, Path, PathBuf};
+
+use thiserror::Error;
+
+use crate::bin::{decrypt_at, decrypt_in_place, sniff, DecryptError, FileKind};
+use crate::name::Name;
+
+/// A file inside an encrypted archive, the table itself lives in the executable.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PakEntry {
+    pub name: Name<'static>,
+    pub offset: usize,
+    pub len: usize,
+}
+
+/// Encrypted archive, entries are decrypted only when asked for.
+/// `D` is where the bytes live, a `Vec` or a memory mapped file with the `mmap` feature.
+pub struct PakArchive<D = Vec<u8>> {
+    data: D,
+    key: u32,
+    entries: Vec<PakEntry>,
+}
+
+impl PakArchive {
+    pub fn open<P: AsRef<std::path::Path>>(
+        path: P,
+        key: u32,
+        entries: Vec<PakEntry>,
+    ) -> Result<Self, DecryptError> {
+        Self::new(std::fs::read(path)?, key, entries)
+    }
+}
+
+#[cfg(feature = "mmap")]
+impl PakArchive<memmap2::Mmap> {
+    /// Maps the archive instead of reading it, see `mmap::map`.
+    pub fn open_mmap<P: AsRef<std::path::Path>>(
+        path: P,
+        key: u32,
+        entries: Vec<PakEntry>,
+    ) -> Result<Self, DecryptError> {
+        Self::new(crate::mmap::map(path)?, key, entries)
+    }
+}
+
+impl<D: AsRef<[u8]>> PakArchive<D> {
+    pub fn new(data: D, key: u32, entries: Vec<PakEntry>) -> Result<Self, DecryptError> {
+        let len = data.as_ref().len();
+        if let Some(e) = entries
+            .iter()
+            .find(|e| e.offset.checked_add(e.len).is_none_or(|end| end > len))
+        {
+            return Err(DecryptError::OutOfBounds {
+                offset: e.offset,
+                len: e.len,
+                size: len,
+            });
+        }
+        Ok(Self { data, key, entries })
+    }
+
+    pub fn key(&self) -> u32 {
+        self.key
+    }
+
+    pub fn entries(&self) -> &[PakEntry] {
+        &self.entries
+    }
+
+    /// Still encrypted bytes of an entry, an error if it doesn't fit in the archive.
+    pub fn raw(&self, entry: &PakEntry) -> Result<&[u8], DecryptError> {
+        let data = self.data.as_ref();
+        entry
+            .offset
+            .checked_add(entry.len)
+            .and_then(|end| data.get(entry.offset..end))
+            .ok_or(DecryptError::OutOfBounds {
+                offset: entry.offset,
+                len: entry.len,
+                size: data.len(),
+            })
+    }
+
+    pub fn read(&self, entry: &PakEntry) -> Result<Vec<u8>, DecryptError> {
+        Ok(decrypt_at(self.raw(entry)?, self.key, entry.offset))
+    }
+
+    /// Like `read` but reuses `out`, so going through a whole archive allocates once.
+    pub fn read_into(&self, entry: &PakEntry, out: &mut Vec<u8>) -> Result<(), DecryptError> {
+        let raw = self.raw(entry)?;
+        out.clear();
+        out.extend_from_slice(raw);
+        decrypt_in_place(out, self.key, entry.offset);
+        Ok(())
+    }
+
+    // hgm and hga chunk chains are only recognised whole, so this decrypts the entire entry
+    pub fn sniff(&self, entry: &PakEntry) -> Result<FileKind, DecryptError> {
+        Ok(sniff(&self.read(entry)?))
+    }
+
+    /// A wrong key turns every entry into garbage, a right one can still leave a few formats
+    /// this crate doesn't know about. Valid when most entries are recognised.
+    pub fn key_looks_valid(&self) -> bool {
+        let mut buf = Vec::new();
+        let known = self
+            .entries
+            .iter()
+            .filter(|e| self.read_into(e, &mut buf).is_ok() && sniff(&buf) != FileKind::Unknown)
+            .count();
+        known * 2 > self.entries.len()
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum ExtractError {
+    #[error("entry name {0:?} doesn't make a safe file name")]
+    UnsafeName(String),
+    #[error("ran out of names for {0:?}")]
+    NoFreeName(String),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+/// What to do when an output file is already taken.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Collision {
+    #[default]
+    Rename,
+    Overwrite,
+    Skip,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Clash {
+    // an earlier entry of the same archive ended up with the same file name
+    Duplicate,
+    // there was a file there before extraction started
+    Exists,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Placement {
+    // None when skipped
+    pub path: Option<PathBuf>,
+    pub clash: Option<Clash>,
+}
+
+// Case insensitive file systems are the common case on Windows
+fn key(file_name: &str) -> String {
+    file_name.to_ascii_lowercase()
+}
+
+/// Where the entries of one archive get extracted to. Names go through `Name::file_name`
+/// and always end up directly inside the directory.
+pub struct OutputDir {
+    root: PathBuf,
+    policy: Collision,
+    used: HashSet<String>,
+}
+
+impl OutputDir {
+    pub fn new<P: Into<PathBuf>>(root: P, policy: Collision) -> Self {
+        Self {
+            root: root.into(),
+            policy,
+            used: HashSet::new(),
+        }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Path for an entry with the given extension, following the collision policy.
+    pub fn place(&mut self, name: &Name, ext: &str) -> Result<Placement, ExtractError> {
+        let stem = name.file_name();
+        let ext = crate::name::safe_file_name(ext);
+        let file_name = format!("{stem}.{ext}");
+        // file_name is already safe, this only guards against that changing
+        let mut components = Path::new(&file_name).components();
+        if !matches!(
+            (components.next(), components.next()),
+            (Some(Component::Normal(_)), None)
+        ) {
+            return Err(ExtractError::UnsafeName(name.to_string()));
+        }
+
+        let path = self.root.join(&file_name);
+        let clash = if self.used.contains(&key(&file_name)) {
+            Some(Clash::Duplicate)
+        } else if path.try_exists()? {
+            Some(Clash::Exists)
+        } else {
+            None
+        };
+        let path = match (clash, self.policy) {
+            (None, _) | (Some(_), Collision::Overwrite) => Some(path),
+            (Some(_), Collision::Skip) => None,
+            (Some(_), Collision::Rename) => Some(self.free_path(&stem, &ext)?),
+        };
+        if let Some(path) = &path {
+            if let Some(x) = path.file_name().and_then(|x| x.to_str()) {
+                self.used.insert(key(x));
+            }
+        }
+        Ok(Placement { path, clash })
+    }
+
+    fn free_path(&self, stem: &str, ext: &str) -> Result<PathBuf, ExtractError> {
+        for i in 1..10000 {
+            let file_name = format!("{stem}_{i}.{ext}");
+            let path = self.root.join(&file_name);
+            if !self.used.contains(&key(&file_name)) && !path.try_exists()? {
+                return Ok(path);
+            }
+        }
+        Err(ExtractError::NoFreeName(format!("{stem}.{ext}")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bin::decrypt_in_place;
+
+    const KEY: u32 = 0x1234_5678;
+
+    fn entry(offset: usize, len: usize) -> PakEntry {
+        PakEntry {
+            name: Name::new(format!("{offset}")),
+            offset,
+            len,
+        }
+    }
+
+    // a one chunk hgm, an unknown blob and another hgm
+    fn archive() -> (Vec<u8>, Vec<PakEntry>) {
+        let mut data = vec![4, 0, 0, 0, 16, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        data.extend([0xAA; 8]);
+        data.extend([0, 0, 0, 0, 8, 0, 0, 0]);
+        decrypt_in_place(&mut data, KEY, 0);
+        (data, vec![entry(0, 16), entry(16, 8), entry(24, 8)])
+    }
+
+    #[test]
+    fn reads_entries() {
+        let (data, entries) = archive();
+        let pak = PakArchive::new(data, KEY, entries.clone()).unwrap();
+        assert_eq!(pak.sniff(&entries[0]).unwrap(), FileKind::Hgm);
+        assert_eq!(pak.sniff(&entries[1]).unwrap(), FileKind::Unknown);
+        assert_eq!(pak.read(&entries[1]).unwrap(), [0xAA; 8]);
+        assert!(pak.key_looks_valid());
+    }
+
+    #[test]
+    fn key_needs_most_entries() {
+        let (data, entries) = archive();
+        let pak = PakArchive::new(data.clone(), KEY, entries[..2].to_vec()).unwrap();
+        assert!(!pak.key_looks_valid());
+        let pak = PakArchive::new(data, KEY ^ 1, entries).unwrap();
+        assert!(!pak.key_looks_valid());
+    }
+
+    #[test]
+    fn out_of_bounds_entries_are_errors() {
+        let (data, entries) = archive();
+        assert!(matches!(
+            PakArchive::new(data.clone(), KEY, vec![entry(0, 8), entry(30, 4)]),
+            Err(DecryptError::OutOfBounds {
+                offset: 30,
+                len: 4,
+                size: 32
+            })
+        ));
+        let pak = PakArchive::new(data, KEY, entries).unwrap();
+        for e in [entry(30, 4), entry(usize::MAX, 2)] {
+            assert!(matches!(pak.raw(&e), Err(DecryptError::OutOfBounds { .. })));
+            assert!(pak.read(&e).is_err());
+            assert!(pak.sniff(&e).is_err());
+            let mut buf = Vec::new();
+            assert!(pak.read_into(&e, &mut buf).is_err());
+        }
+    }
+
+    // Fresh directory under the system temp dir, gone when dropped
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("pak-{name}-{}", std::process::id()));
+            let _ = std::fs::remove_dir_all(&path);
+            std::fs::create_dir_all(&path).unwrap();
+            Self(path)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn file_name(placement: &Placement) -> &str {
+        placement
+            .path
+            .as_ref()
+            .and_then(|x| x.file_name())
+            .and_then(|x| x.to_str())
+            .unwrap()
+    }
+
+    #[test]
+    fn places_inside_the_root() {
+        let dir = TempDir::new("inside");
+        let mut out = OutputDir::new(&dir.0, Collision::Rename);
+        for name in [
+            "../evil",
+            "/etc/passwd",
+            "a\\b",
+            "C:\\x",
+            "CON",
+            "",
+            "..",
+            "   ",
+        ] {
+            let placement = out.place(&Name::new(name), "bin").unwrap();
+            let path = placement.path.unwrap();
+            assert_eq!(path.parent(), Some(dir.0.as_path()), "{name:?}");
+        }
+        // the extension is cleaned up too
+        let placement = out.place(&Name::new("x"), "../y").unwrap();
+        assert_eq!(file_name(&placement), "x._._y");
+    }
+
+    #[test]
+    fn renames_collisions() {
+        let dir = TempDir::new("rename");
+        std::fs::write(dir.0.join("old.bin"), []).unwrap();
+        let mut out = OutputDir::new(&dir.0, Collision::Rename);
+
+        let a = out.place(&Name::new("a"), "bin").unwrap();
+        assert_eq!((file_name(&a), a.clash), ("a.bin", None));
+        // case only differs, still the same file on Windows
+        let b = out.place(&Name::new("A"), "BIN").unwrap();
+        assert_eq!(
+            (file_name(&b), b.clash),
+            ("A_1.BIN", Some(Clash::Duplicate))
+        );
+        let c = out.place(&Name::new("a"), "bin").unwrap();
+        assert_eq!(
+            (file_name(&c), c.clash),
+            ("a_2.bin", Some(Clash::Duplicate))
+        );
+        let old = out.place(&Name::new("old"), "bin").unwrap();
+        assert_eq!(
+            (file_name(&old), old.clash),
+            ("old_1.bin", Some(Clash::Exists))
+        );
+    }
+
+    #[test]
+    fn skips_collisions() {
+        let dir = TempDir::new("skip");
+        std::fs::write(dir.0.join("old.bin"), []).unwrap();
+        let mut out = OutputDir::new(&dir.0, Collision::Skip);
+
+        let a = out.place(&Name::new("a"), "bin").unwrap();
+        assert_eq!((file_name(&a), a.clash), ("a.bin", None));
+        let b = out.place(&Name::new("A"), "bin").unwrap();
+        assert_eq!((b.path, b.clash), (None, Some(Clash::Duplicate)));
+        let old = out.place(&Name::new("old"), "bin").unwrap();
+        assert_eq!((old.path, old.clash), (None, Some(Clash::Exists)));
+    }
+
+    #[test]
+    fn overwrites_collisions() {
+        let dir = TempDir::new("overwrite");
+        std::fs::write(dir.0.join("old.bin"), []).unwrap();
+        let mut out = OutputDir::new(&dir.0, Collision::Overwrite);
+
+        let a = out.place(&Name::new("a"), "bin").unwrap();
+        let b = out.place(&Name::new("A"), "bin").unwrap();
+        assert_eq!(b.clash, Some(Clash::Duplicate));
+        assert_eq!(file_name(&b), "A.bin");
+        assert_ne!(a.path, None);
+        let old = out.place(&Name::new("old"), "bin").unwrap();
+        assert_eq!(
+            (file_name(&old), old.clash),
+            ("old.bin", Some(Clash::Exists))
+        );
+    }
+}
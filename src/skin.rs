@@ -0,0 +1,356 @@
+use thiserror::Error;
+
+use crate::hg::{GeometryBlock, VertexFeatures};
+use crate::le;
+use crate::math::{self, Mat4};
+use crate::model::Model;
+
+#[derive(Error, Debug)]
+pub enum SkinError {
+    #[error("geometry has no vertex data")]
+    NoVertexData,
+    #[error("geometry has no positions")]
+    NoPositions,
+    #[error("vertex {0} points at bone {1} which doesn't exist")]
+    BadBoneIndex(usize, usize),
+    #[error("pose has {0} bones, skeleton has {1}")]
+    PoseMismatch(usize, usize),
+    #[error("bone {0} has a singular matrix")]
+    Singular(usize),
+    #[error("vertices are {0} bytes, the vertex mask needs {1}")]
+    VertexSize(usize, usize),
+    #[error("the skeleton's hierarchy isn't known")]
+    NoHierarchy,
+}
+
+#[derive(Debug, Clone)]
+pub struct Joint {
+    pub name: String,
+    // Model space to bone space. Guess: `BoneBlock::matrix` is the D3DX style offset matrix
+    pub inverse_bind: Mat4,
+    // Bone space to model space
+    pub bind: Mat4,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Skeleton {
+    pub joints: Vec<Joint>,
+    // One per joint, None for roots. Only known when the model has a `Hierarchy`: `BoneBlock::idk`
+    // would be the obvious candidate but nothing confirms it's a parent index (the inspect_model
+    // example checks that on real files), so it isn't used.
+    pub parents: Option<Vec<Option<usize>>>,
+}
+
+/// World (model space) matrix for every joint of a skeleton.
+#[derive(Debug, Clone)]
+pub struct Pose {
+    pub world: Vec<Mat4>,
+}
+
+impl Skeleton {
+    pub fn from_model(model: &Model) -> Result<Self, SkinError> {
+        let joints = model
+            .skeleton
+            .iter()
+            .enumerate()
+            .map(|(i, bone)| {
+                Ok(Joint {
+                    name: bone.block.name.to_string(),
+                    inverse_bind: bone.block.to_matrix(),
+                    bind: bone.block.inverse().ok_or(SkinError::Singular(i))?,
+                })
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            joints,
+            parents: model.hierarchy().map(|x| x.bone_parents.clone()),
+        })
+    }
+
+    pub fn find(&self, name: &str) -> Option<usize> {
+        self.joints.iter().position(|x| x.name == name)
+    }
+
+    pub fn bind_pose(&self) -> Pose {
+        Pose {
+            world: self.joints.iter().map(|x| x.bind).collect(),
+        }
+    }
+
+    /// Chains local (parent space) matrices up the hierarchy, which has to be known.
+    pub fn pose_from_local(&self, local: &[Mat4]) -> Result<Pose, SkinError> {
+        if local.len() != self.joints.len() {
+            return Err(SkinError::PoseMismatch(local.len(), self.joints.len()));
+        }
+        if local.is_empty() {
+            return Ok(Pose { world: Vec::new() });
+        }
+        let parents = self.parents.as_ref().ok_or(SkinError::NoHierarchy)?;
+        if parents.len() != self.joints.len() {
+            return Err(SkinError::NoHierarchy);
+        }
+        let mut world: Vec<Option<Mat4>> = vec![None; local.len()];
+        for i in 0..local.len() {
+            // walk up until something is known, parents don't have to come first
+            let mut chain = vec![i];
+            while let Some(parent) = parents[*chain.last().unwrap()] {
+                if world[parent].is_some() || chain.contains(&parent) {
+                    break;
+                }
+                chain.push(parent);
+            }
+            for &j in chain.iter().rev() {
+                if world[j].is_some() {
+                    continue;
+                }
+                let parent = parents[j].and_then(|p| world[p]);
+                world[j] = Some(match parent {
+                    Some(parent) => math::mul(&local[j], &parent),
+                    None => local[j],
+                });
+            }
+        }
+        Ok(Pose {
+            world: world.into_iter().map(Option::unwrap_or_default).collect(),
+        })
+    }
+
+    /// What every vertex gets multiplied with, bind pose gives identities.
+    pub fn skin_matrices(&self, pose: &Pose) -> Result<Vec<Mat4>, SkinError> {
+        if pose.world.len() != self.joints.len() {
+            return Err(SkinError::PoseMismatch(pose.world.len(), self.joints.len()));
+        }
+        Ok(self
+            .joints
+            .iter()
+            .zip(&pose.world)
+            .map(|(joint, world)| math::mul(&joint.inverse_bind, world))
+            .collect())
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Skinned {
+    pub positions: Vec<[f32; 3]>,
+    pub normals: Vec<[f32; 3]>,
+}
+
+fn read_f32s<const N: usize>(data: &[u8], at: usize) -> [f32; N] {
+    le::read(data, at)
+}
+
+/// Poses `geometry` on the CPU, vertices without weights follow bone 0.
+/// Without any joints nothing moves, the vertices come back as they are.
+pub fn skin(
+    geometry: &GeometryBlock,
+    skeleton: &Skeleton,
+    pose: &Pose,
+) -> Result<Skinned, SkinError> {
+    let matrices = skeleton.skin_matrices(pose)?;
+    let mask = geometry.vertex_bitmask;
+    let data = geometry
+        .vertex_data
+        .as_deref()
+        .ok_or(SkinError::NoVertexData)?;
+    let stride = geometry.stride().ok_or(SkinError::VertexSize(
+        geometry.vertex_size.unwrap_or_default(),
+        mask.vertex_size(),
+    ))?;
+    let count = geometry
+        .vertex_num
+        .map(|x| x as usize)
+        .unwrap_or(data.len() / stride.max(1));
+    if count.checked_mul(stride).is_none_or(|x| x > data.len()) {
+        return Err(SkinError::NoVertexData);
+    }
+    let position = mask
+        .offset_of(VertexFeatures::Position)
+        .ok_or(SkinError::NoPositions)?;
+    // every read below stays inside its vertex
+    let end = mask
+        .layout()
+        .last()
+        .map_or(0, |&(x, offset)| offset + x.size());
+    if stride < position + 12 || stride < end {
+        return Err(SkinError::VertexSize(stride, end.max(position + 12)));
+    }
+    let normal = mask.offset_of(VertexFeatures::Normal);
+    let weights = [
+        VertexFeatures::Weight0,
+        VertexFeatures::Weight1,
+        VertexFeatures::Weight2,
+        VertexFeatures::Weight3,
+    ]
+    .map(|x| mask.offset_of(x));
+    let indices = mask.offset_of(VertexFeatures::WeightIndicies);
+
+    let mut ret = Skinned::default();
+    for v in 0..count {
+        let base = v * stride;
+        if matrices.is_empty() {
+            ret.positions.push(read_f32s(data, base + position));
+            if let Some(at) = normal {
+                ret.normals.push(read_f32s(data, base + at));
+            }
+            continue;
+        }
+        // guess: indices are floats, see the importer
+        let bones = match indices {
+            Some(at) => read_f32s::<4>(data, base + at).map(|x| x as usize),
+            None => [0, 1, 2, 3],
+        };
+        // D3D style, the weight after the last stored one makes the sum 1
+        let mut w = [0f32; 4];
+        let mut stored = 0;
+        for (i, at) in weights.iter().enumerate() {
+            if let Some(at) = at {
+                w[i] = read_f32s::<1>(data, base + at)[0];
+                stored = i + 1;
+            }
+        }
+        if stored < 4 {
+            w[stored] = 1.0 - w[..stored].iter().sum::<f32>();
+        }
+
+        let mut m = [[0f32; 4]; 4];
+        for (&bone, &weight) in bones.iter().zip(&w) {
+            if weight == 0.0 {
+                continue;
+            }
+            let matrix = matrices.get(bone).ok_or(SkinError::BadBoneIndex(v, bone))?;
+            m = math::add(&m, &math::scale(matrix, weight));
+        }
+
+        ret.positions
+            .push(math::transform_point(&m, read_f32s(data, base + position)));
+        if let Some(at) = normal {
+            // no inverse transpose, fine as long as bones don't scale unevenly
+            let [x, y, z] = math::transform_vector(&m, read_f32s(data, base + at));
+            let len = (x * x + y * y + z * z).sqrt().max(f32::EPSILON);
+            ret.normals.push([x / len, y / len, z / len]);
+        }
+    }
+    Ok(ret)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::Hierarchy;
+
+    const QUAD: &[u8] = include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/quad.hgm"));
+
+    fn translation(x: f32, y: f32, z: f32) -> Mat4 {
+        let mut ret = math::IDENTITY;
+        ret[3] = [x, y, z, 1.0];
+        ret
+    }
+
+    fn close(a: &[[f32; 3]], b: &[[f32; 3]]) -> bool {
+        a.len() == b.len()
+            && a.iter()
+                .flatten()
+                .zip(b.iter().flatten())
+                .all(|(x, y)| (x - y).abs() < 1e-5)
+    }
+
+    #[test]
+    fn empty_skeleton_is_identity() {
+        let model = Model::read(QUAD).unwrap();
+        let geometry = &model.meshes[0].geometry;
+        let skeleton = Skeleton::default();
+        let skinned = skin(geometry, &skeleton, &skeleton.bind_pose()).unwrap();
+        assert_eq!(skinned.positions, geometry.positions().unwrap());
+        assert_eq!(skinned.normals, vec![[0.0, 0.0, 1.0]; 4]);
+        let posed = skeleton.pose_from_local(&[]).unwrap();
+        assert!(skin(geometry, &skeleton, &posed).is_ok());
+    }
+
+    #[test]
+    fn stale_vertex_size_is_an_error() {
+        let model = Model::read(QUAD).unwrap();
+        let mut geometry = model.meshes[0].geometry.clone();
+        let skeleton = Skeleton::default();
+        geometry.vertex_bitmask.unset(VertexFeatures::Normal);
+        assert!(geometry.positions().is_none());
+        assert!(matches!(
+            skin(&geometry, &skeleton, &skeleton.bind_pose()),
+            Err(SkinError::VertexSize(32, 20))
+        ));
+        // positions alone fit in the data, read with the mask's stride
+        geometry.vertex_size = None;
+        let skinned = skin(&geometry, &skeleton, &skeleton.bind_pose()).unwrap();
+        assert_eq!(skinned.positions, geometry.positions().unwrap());
+        assert!(skinned.normals.is_empty());
+    }
+
+    #[test]
+    fn bind_pose_leaves_vertices_alone() {
+        let model = Model::read(QUAD).unwrap();
+        let geometry = &model.meshes[0].geometry;
+        let skeleton = Skeleton::from_model(&model).unwrap();
+        assert_eq!(skeleton.joints[0].bind, translation(-1.0, -2.0, -3.0));
+        let skinned = skin(geometry, &skeleton, &skeleton.bind_pose()).unwrap();
+        assert!(close(&skinned.positions, &geometry.positions().unwrap()));
+
+        // vertices without weights follow bone 0
+        let pose = Pose {
+            world: vec![math::mul(
+                &skeleton.joints[0].bind,
+                &translation(0.0, 0.0, 2.0),
+            )],
+        };
+        let skinned = skin(geometry, &skeleton, &pose).unwrap();
+        let moved: Vec<_> = geometry
+            .positions()
+            .unwrap()
+            .iter()
+            .map(|&[x, y, z]| [x, y, z + 2.0])
+            .collect();
+        assert!(close(&skinned.positions, &moved));
+    }
+
+    #[test]
+    fn posing_needs_the_hierarchy() {
+        let mut model = Model::read(QUAD).unwrap();
+        let skeleton = Skeleton::from_model(&model).unwrap();
+        assert!(skeleton.parents.is_none());
+        assert!(matches!(
+            skeleton.pose_from_local(&[math::IDENTITY]),
+            Err(SkinError::NoHierarchy)
+        ));
+
+        model.set_hierarchy(Some(Hierarchy::flat(&model))).unwrap();
+        let skeleton = Skeleton::from_model(&model).unwrap();
+        let local = translation(1.0, 0.0, 0.0);
+        assert_eq!(skeleton.pose_from_local(&[local]).unwrap().world, [local]);
+    }
+
+    #[test]
+    fn local_matrices_chain_up() {
+        let joint = |name: &str| Joint {
+            name: name.to_string(),
+            inverse_bind: math::IDENTITY,
+            bind: math::IDENTITY,
+        };
+        // children before their parents on purpose
+        let skeleton = Skeleton {
+            joints: vec![joint("hand"), joint("arm"), joint("root")],
+            parents: Some(vec![Some(1), Some(2), None]),
+        };
+        let local = [
+            translation(0.0, 0.0, 1.0),
+            translation(0.0, 1.0, 0.0),
+            translation(1.0, 0.0, 0.0),
+        ];
+        let pose = skeleton.pose_from_local(&local).unwrap();
+        assert_eq!(
+            pose.world,
+            [
+                translation(1.0, 1.0, 1.0),
+                translation(1.0, 1.0, 0.0),
+                translation(1.0, 0.0, 0.0)
+            ]
+        );
+    }
+}
@@ -0,0 +1,694 @@
+use thiserror::Error;
+
+use crate::le;
+
+pub const WAVE_FORMAT_PCM: u16 = 1;
+pub const WAVE_FORMAT_ADPCM: u16 = 2;
+pub const WAVE_FORMAT_IEEE_FLOAT: u16 = 3;
+pub const WAVE_FORMAT_IMA_ADPCM: u16 = 0x11;
+pub const WAVE_FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+
+#[derive(Error, Debug)]
+pub enum SoundError {
+    #[error("data too small, got {0} bytes")]
+    TooSmall(usize),
+    #[error("not a RIFF/WAVE file")]
+    NotWave,
+    #[error("missing `{0}` chunk")]
+    MissingChunk(&'static str),
+    #[error("unsupported format {0:#X} with {1} bits per sample")]
+    UnsupportedFormat(u16, u16),
+    #[error("bad `{0}` chunk")]
+    BadChunk(&'static str),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WavFormat {
+    pub format_tag: u16,
+    pub channels: u16,
+    pub sample_rate: u32,
+    pub byte_rate: u32,
+    pub block_align: u16,
+    pub bits_per_sample: u16,
+    // whatever follows cbSize, ADPCM keeps its block info there
+    pub extra: Vec<u8>,
+}
+
+impl WavFormat {
+    fn read(src: &[u8]) -> Result<Self, SoundError> {
+        if src.len() < 16 {
+            return Err(SoundError::BadChunk("fmt "));
+        }
+        let u16_at = |i: usize| le::read::<u16>(src, i);
+        let u32_at = |i: usize| le::read::<u32>(src, i);
+        let extra = if src.len() >= 18 {
+            let size = u16_at(16) as usize;
+            src.get(18..18 + size)
+                .ok_or(SoundError::BadChunk("fmt "))?
+                .to_vec()
+        } else {
+            Vec::new()
+        };
+        let mut ret = Self {
+            format_tag: u16_at(0),
+            channels: u16_at(2),
+            sample_rate: u32_at(4),
+            byte_rate: u32_at(8),
+            block_align: u16_at(12),
+            bits_per_sample: u16_at(14),
+            extra,
+        };
+        // The real format is the first 2 bytes of the sub format GUID
+        if ret.format_tag == WAVE_FORMAT_EXTENSIBLE && ret.extra.len() >= 8 {
+            ret.format_tag = le::read(&ret.extra, 6);
+        }
+        if ret.channels == 0 || ret.block_align == 0 {
+            return Err(SoundError::BadChunk("fmt "));
+        }
+        Ok(ret)
+    }
+
+    // ADPCM formats store it right after cbSize
+    pub fn samples_per_block(&self) -> Option<usize> {
+        match self.format_tag {
+            WAVE_FORMAT_ADPCM | WAVE_FORMAT_IMA_ADPCM if self.extra.len() >= 2 => {
+                Some(le::read::<u16>(&self.extra, 0) as usize)
+            }
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Chunk<'a> {
+    pub id: [u8; 4],
+    pub data: &'a [u8],
+}
+
+/// Entry of the `smpl` chunk, positions are in sample frames.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SampleLoop {
+    pub cue_id: u32,
+    // 0 forward, 1 ping-pong, 2 backward
+    pub typ: u32,
+    pub start: u32,
+    pub end: u32,
+    pub fraction: u32,
+    // 0 is forever
+    pub play_count: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CuePoint {
+    pub id: u32,
+    pub position: u32,
+    pub chunk_id: [u8; 4],
+    pub chunk_start: u32,
+    pub block_start: u32,
+    pub sample_offset: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct Wav<'a> {
+    pub format: WavFormat,
+    pub data: &'a [u8],
+    // from `fact`, compressed formats need it for an exact length
+    pub sample_frames: Option<u32>,
+    pub loops: Vec<SampleLoop>,
+    pub cues: Vec<CuePoint>,
+    pub chunks: Vec<Chunk<'a>>,
+}
+
+fn u32_at(src: &[u8], i: usize) -> u32 {
+    le::read(src, i)
+}
+
+pub fn read_chunks(src: &[u8]) -> Result<Vec<Chunk<'_>>, SoundError> {
+    if src.len() < 12 {
+        return Err(SoundError::TooSmall(src.len()));
+    }
+    if &src[..4] != b"RIFF" || &src[8..12] != b"WAVE" {
+        return Err(SoundError::NotWave);
+    }
+    // trust the data over the RIFF size, some tools get it wrong
+    let mut cursor = &src[12..];
+    let mut ret = Vec::new();
+    while cursor.len() >= 8 {
+        let id = cursor[..4].try_into().unwrap();
+        let size = u32_at(cursor, 4) as usize;
+        let data = cursor
+            .get(8..8 + size)
+            .ok_or(SoundError::TooSmall(src.len()))?;
+        ret.push(Chunk { id, data });
+        // chunks are padded to 2 bytes
+        cursor = cursor.get(8 + size + (size & 1)..).unwrap_or_default();
+    }
+    Ok(ret)
+}
+
+impl<'a> Wav<'a> {
+    pub fn parse(src: &'a [u8]) -> Result<Self, SoundError> {
+        let chunks = read_chunks(src)?;
+        let find = |id: &[u8; 4]| chunks.iter().find(|c| &c.id == id).map(|c| c.data);
+
+        let format = WavFormat::read(find(b"fmt ").ok_or(SoundError::MissingChunk("fmt "))?)?;
+        let data = find(b"data").ok_or(SoundError::MissingChunk("data"))?;
+        let sample_frames = find(b"fact").filter(|x| x.len() >= 4).map(|x| u32_at(x, 0));
+
+        let mut loops = Vec::new();
+        if let Some(smpl) = find(b"smpl") {
+            if smpl.len() < 36 {
+                return Err(SoundError::BadChunk("smpl"));
+            }
+            let count = u32_at(smpl, 28) as usize;
+            let entries = smpl
+                .get(36..36 + count.saturating_mul(24))
+                .ok_or(SoundError::BadChunk("smpl"))?;
+            loops = entries
+                .chunks_exact(24)
+                .map(|x| SampleLoop {
+                    cue_id: u32_at(x, 0),
+                    typ: u32_at(x, 4),
+                    start: u32_at(x, 8),
+                    end: u32_at(x, 12),
+                    fraction: u32_at(x, 16),
+                    play_count: u32_at(x, 20),
+                })
+                .collect();
+        }
+
+        let mut cues = Vec::new();
+        if let Some(cue) = find(b"cue ") {
+            if cue.len() < 4 {
+                return Err(SoundError::BadChunk("cue "));
+            }
+            let count = u32_at(cue, 0) as usize;
+            let entries = cue
+                .get(4..4 + count.saturating_mul(24))
+                .ok_or(SoundError::BadChunk("cue "))?;
+            cues = entries
+                .chunks_exact(24)
+                .map(|x| CuePoint {
+                    id: u32_at(x, 0),
+                    position: u32_at(x, 4),
+                    chunk_id: x[8..12].try_into().unwrap(),
+                    chunk_start: u32_at(x, 12),
+                    block_start: u32_at(x, 16),
+                    sample_offset: u32_at(x, 20),
+                })
+                .collect();
+        }
+
+        let ret = Self {
+            format,
+            data,
+            sample_frames,
+            loops,
+            cues,
+            chunks,
+        };
+        // makes sure the decoders below know what to do with it
+        ret.kind()?;
+        Ok(ret)
+    }
+
+    fn kind(&self) -> Result<SampleKind, SoundError> {
+        let format = &self.format;
+        let kind = match (format.format_tag, format.bits_per_sample) {
+            (WAVE_FORMAT_PCM, 8) => SampleKind::U8,
+            (WAVE_FORMAT_PCM, 16) => SampleKind::I16,
+            (WAVE_FORMAT_PCM, 24) => SampleKind::I24,
+            (WAVE_FORMAT_PCM, 32) => SampleKind::I32,
+            (WAVE_FORMAT_IEEE_FLOAT, 32) => SampleKind::F32,
+            (WAVE_FORMAT_IMA_ADPCM, 4) => SampleKind::ImaAdpcm,
+            (WAVE_FORMAT_ADPCM, 4) => SampleKind::MsAdpcm,
+            (tag, bits) => return Err(SoundError::UnsupportedFormat(tag, bits)),
+        };
+        let channels = format.channels as usize;
+        let block_align = format.block_align as usize;
+        let header = match kind {
+            SampleKind::ImaAdpcm => 4,
+            SampleKind::MsAdpcm => 7,
+            _ => 0,
+        };
+        let frame_size = (format.bits_per_sample as usize / 8) * channels;
+        if (header == 0 && block_align != frame_size) || block_align < header * channels {
+            return Err(SoundError::BadChunk("fmt "));
+        }
+        Ok(kind)
+    }
+
+    pub fn channels(&self) -> u16 {
+        self.format.channels
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.format.sample_rate
+    }
+
+    pub fn bits_per_sample(&self) -> u16 {
+        self.format.bits_per_sample
+    }
+
+    /// Samples per channel.
+    pub fn frames(&self) -> usize {
+        if let Some(frames) = self.sample_frames {
+            return frames as usize;
+        }
+        let block_align = self.format.block_align as usize;
+        match self.format.samples_per_block() {
+            Some(per_block) => {
+                // a partial last block still decodes to something
+                let full = self.data.len() / block_align;
+                let rest = self.data.len() % block_align;
+                let channels = self.format.channels as usize;
+                let partial = match self.kind() {
+                    Ok(SampleKind::ImaAdpcm) if rest >= 4 * channels => {
+                        1 + (rest - 4 * channels) * 2 / channels
+                    }
+                    Ok(SampleKind::MsAdpcm) if rest >= 7 * channels => {
+                        2 + (rest - 7 * channels) * 2 / channels
+                    }
+                    _ => 0,
+                };
+                full * per_block + partial
+            }
+            None => self.data.len() / block_align,
+        }
+    }
+
+    pub fn duration(&self) -> std::time::Duration {
+        if self.format.sample_rate == 0 {
+            return std::time::Duration::ZERO;
+        }
+        std::time::Duration::from_secs_f64(self.frames() as f64 / self.format.sample_rate as f64)
+    }
+
+    /// Interleaved samples.
+    pub fn decode_i16(&self) -> Result<Vec<i16>, SoundError> {
+        let data = self.data;
+        Ok(match self.kind()? {
+            SampleKind::I16 => le::read_all(data).collect(),
+            SampleKind::ImaAdpcm => self.decode_adpcm(decode_ima_block),
+            SampleKind::MsAdpcm => self.decode_adpcm(decode_ms_block),
+            _ => self
+                .decode_f32()?
+                .into_iter()
+                .map(|x| (x * 32768.0).round().clamp(-32768.0, 32767.0) as i16)
+                .collect(),
+        })
+    }
+
+    /// Interleaved samples in -1..1.
+    pub fn decode_f32(&self) -> Result<Vec<f32>, SoundError> {
+        let data = self.data;
+        Ok(match self.kind()? {
+            SampleKind::U8 => data.iter().map(|&x| (x as f32 - 128.0) / 128.0).collect(),
+            SampleKind::I24 => data
+                .chunks_exact(3)
+                .map(|x| {
+                    let v = le::read::<i32>(&[0, x[0], x[1], x[2]], 0) >> 8;
+                    v as f32 / 8388608.0
+                })
+                .collect(),
+            SampleKind::I32 => le::read_all::<i32>(data)
+                .map(|x| x as f32 / 2147483648.0)
+                .collect(),
+            SampleKind::F32 => le::read_all(data).collect(),
+            SampleKind::I16 | SampleKind::ImaAdpcm | SampleKind::MsAdpcm => self
+                .decode_i16()?
+                .into_iter()
+                .map(|x| x as f32 / 32768.0)
+                .collect(),
+        })
+    }
+
+    fn decode_adpcm(&self, decode_block: fn(&WavFormat, &[u8], &mut Vec<i16>)) -> Vec<i16> {
+        let mut ret = Vec::new();
+        for block in self.data.chunks(self.format.block_align as usize) {
+            decode_block(&self.format, block, &mut ret);
+        }
+        ret.truncate(self.frames() * self.format.channels as usize);
+        ret
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SampleKind {
+    U8,
+    I16,
+    I24,
+    I32,
+    F32,
+    ImaAdpcm,
+    MsAdpcm,
+}
+
+const IMA_INDEX_TABLE: [i32; 16] = [-1, -1, -1, -1, 2, 4, 6, 8, -1, -1, -1, -1, 2, 4, 6, 8];
+const IMA_STEP_TABLE: [i32; 89] = [
+    7, 8, 9, 10, 11, 12, 13, 14, 16, 17, 19, 21, 23, 25, 28, 31, 34, 37, 41, 45, 50, 55, 60, 66,
+    73, 80, 88, 97, 107, 118, 130, 143, 157, 173, 190, 209, 230, 253, 279, 307, 337, 371, 408, 449,
+    494, 544, 598, 658, 724, 796, 876, 963, 1060, 1166, 1282, 1411, 1552, 1707, 1878, 2066, 2272,
+    2499, 2749, 3024, 3327, 3660, 4026, 4428, 4871, 5358, 5894, 6484, 7132, 7845, 8630, 9493,
+    10442, 11487, 12635, 13899, 15289, 16818, 18500, 20350, 22385, 24623, 27086, 29794, 32767,
+];
+
+// Block: per channel i16 sample + step index + pad, then 4 bytes (8 nibbles) per channel in turn
+fn decode_ima_block(format: &WavFormat, block: &[u8], out: &mut Vec<i16>) {
+    let channels = format.channels as usize;
+    if block.len() < 4 * channels {
+        return;
+    }
+    let mut predictor = Vec::with_capacity(channels);
+    let mut index = Vec::with_capacity(channels);
+    for c in 0..channels {
+        predictor.push(le::read::<i16>(block, 4 * c) as i32);
+        index.push((block[4 * c + 2] as i32).clamp(0, 88));
+    }
+    for &p in &predictor {
+        out.push(p as i16);
+    }
+
+    let data = &block[4 * channels..];
+    let groups = data.len() / (4 * channels);
+    let mut decoded = vec![Vec::with_capacity(groups * 8); channels];
+    for (i, group) in data.chunks_exact(4).take(groups * channels).enumerate() {
+        let c = i % channels;
+        for &b in group {
+            for nibble in [b & 0xF, b >> 4] {
+                let step = IMA_STEP_TABLE[index[c] as usize];
+                let mut diff = step >> 3;
+                if nibble & 1 != 0 {
+                    diff += step >> 2;
+                }
+                if nibble & 2 != 0 {
+                    diff += step >> 1;
+                }
+                if nibble & 4 != 0 {
+                    diff += step;
+                }
+                if nibble & 8 != 0 {
+                    diff = -diff;
+                }
+                predictor[c] = (predictor[c] + diff).clamp(-32768, 32767);
+                index[c] = (index[c] + IMA_INDEX_TABLE[nibble as usize]).clamp(0, 88);
+                decoded[c].push(predictor[c] as i16);
+            }
+        }
+    }
+    for i in 0..groups * 8 {
+        for channel in &decoded {
+            out.push(channel[i]);
+        }
+    }
+}
+
+const MS_ADAPTATION_TABLE: [i32; 16] = [
+    230, 230, 230, 230, 307, 409, 512, 614, 768, 614, 512, 409, 307, 230, 230, 230,
+];
+const MS_DEFAULT_COEFS: [(i32, i32); 7] = [
+    (256, 0),
+    (512, -256),
+    (0, 0),
+    (192, 64),
+    (240, 0),
+    (460, -208),
+    (392, -232),
+];
+
+// Block: predictor indices, deltas, sample1s, sample2s for every channel, then interleaved nibbles
+fn decode_ms_block(format: &WavFormat, block: &[u8], out: &mut Vec<i16>) {
+    let channels = format.channels as usize;
+    if block.len() < 7 * channels {
+        return;
+    }
+    // coefficient table lives after samples per block and its count
+    let coefs: Vec<(i32, i32)> = if format.extra.len() >= 4 {
+        le::read_all::<[i16; 2]>(&format.extra[4..])
+            .map(|[a, b]| (a as i32, b as i32))
+            .collect()
+    } else {
+        Vec::new()
+    };
+    let coefs = if coefs.is_empty() {
+        &MS_DEFAULT_COEFS[..]
+    } else {
+        &coefs[..]
+    };
+
+    let i16_at = |i: usize| le::read::<i16>(block, i) as i32;
+    let mut coef = Vec::with_capacity(channels);
+    let mut delta = Vec::with_capacity(channels);
+    let mut sample1 = Vec::with_capacity(channels);
+    let mut sample2 = Vec::with_capacity(channels);
+    for c in 0..channels {
+        coef.push(coefs[(block[c] as usize).min(coefs.len() - 1)]);
+        delta.push(i16_at(channels + 2 * c));
+        sample1.push(i16_at(3 * channels + 2 * c));
+        sample2.push(i16_at(5 * channels + 2 * c));
+    }
+    // the header holds the first two samples, oldest last
+    for &s in &sample2 {
+        out.push(s as i16);
+    }
+    for &s in &sample1 {
+        out.push(s as i16);
+    }
+
+    let mut c = 0;
+    for &b in &block[7 * channels..] {
+        for nibble in [b >> 4, b & 0xF] {
+            let signed = ((nibble as i8) << 4 >> 4) as i32;
+            let (c1, c2) = coef[c];
+            let predicted = (sample1[c] * c1 + sample2[c] * c2) >> 8;
+            let sample = (predicted + signed * delta[c]).clamp(-32768, 32767);
+            sample2[c] = sample1[c];
+            sample1[c] = sample;
+            delta[c] = ((MS_ADAPTATION_TABLE[nibble as usize] * delta[c]) >> 8).max(16);
+            out.push(sample as i16);
+            c = (c + 1) % channels;
+        }
+    }
+}
+
+/// Flags data that won't play, on top of what `bin::FileKind::check` looks at.
+pub fn validate(src: &[u8]) -> Result<(), SoundError> {
+    let wav = Wav::parse(src)?;
+    let frames = wav.frames() as u32;
+    for l in &wav.loops {
+        if l.start > l.end || l.end > frames {
+            return Err(SoundError::BadChunk("smpl"));
+        }
+    }
+    for c in &wav.cues {
+        if c.sample_offset > frames {
+            return Err(SoundError::BadChunk("cue "));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fmt(tag: u16, channels: u16, block_align: u16, bits: u16, extra: &[u8]) -> Vec<u8> {
+        let mut ret = Vec::new();
+        le::push(&mut ret, tag);
+        le::push(&mut ret, channels);
+        le::push(&mut ret, 8000u32);
+        le::push(&mut ret, 8000 * block_align as u32);
+        le::push(&mut ret, block_align);
+        le::push(&mut ret, bits);
+        if !extra.is_empty() {
+            le::push(&mut ret, extra.len() as u16);
+            ret.extend_from_slice(extra);
+        }
+        ret
+    }
+
+    fn riff(chunks: &[(&[u8; 4], &[u8])]) -> Vec<u8> {
+        let mut ret = b"RIFF\0\0\0\0WAVE".to_vec();
+        for (id, data) in chunks {
+            ret.extend_from_slice(*id);
+            le::push(&mut ret, data.len() as u32);
+            ret.extend_from_slice(data);
+            if data.len() & 1 != 0 {
+                ret.push(0);
+            }
+        }
+        let size = ret.len() as u32 - 8;
+        le::write(&mut ret, 4, size);
+        ret
+    }
+
+    fn pcm(channels: u16, bits: u16, data: &[u8]) -> Vec<u8> {
+        let format = fmt(WAVE_FORMAT_PCM, channels, channels * bits / 8, bits, &[]);
+        riff(&[(b"fmt ", &format), (b"data", data)])
+    }
+
+    #[test]
+    fn decodes_8_bit() {
+        let src = pcm(1, 8, &[0, 128, 255]);
+        let wav = Wav::parse(&src).unwrap();
+        assert_eq!(wav.decode_f32().unwrap(), [-1.0, 0.0, 127.0 / 128.0]);
+        assert_eq!(wav.decode_i16().unwrap(), [-32768, 0, 32512]);
+    }
+
+    #[test]
+    fn decodes_16_bit() {
+        let src = pcm(2, 16, &[0, 0, 0xff, 0x7f, 0, 0x80, 0, 0x40]);
+        let wav = Wav::parse(&src).unwrap();
+        assert_eq!(wav.decode_i16().unwrap(), [0, 32767, -32768, 16384]);
+        assert_eq!(
+            wav.decode_f32().unwrap(),
+            [0.0, 32767.0 / 32768.0, -1.0, 0.5]
+        );
+        assert_eq!(wav.frames(), 2);
+    }
+
+    #[test]
+    fn decodes_24_bit() {
+        let src = pcm(
+            1,
+            24,
+            &[0, 0, 0x80, 0xff, 0xff, 0x7f, 0, 0, 0x40, 0xff, 0xff, 0xff],
+        );
+        let wav = Wav::parse(&src).unwrap();
+        assert_eq!(
+            wav.decode_f32().unwrap(),
+            [-1.0, 8388607.0 / 8388608.0, 0.5, -1.0 / 8388608.0]
+        );
+        assert_eq!(wav.decode_i16().unwrap(), [-32768, 32767, 16384, 0]);
+    }
+
+    #[test]
+    fn decodes_ima_adpcm() {
+        // 9 samples per block: the header one and 8 nibbles
+        let format = fmt(WAVE_FORMAT_IMA_ADPCM, 1, 8, 4, &[9, 0]);
+        let data = [0, 0, 0, 0, 0x77, 0x77, 0x00, 0x00];
+        let src = riff(&[(b"fmt ", &format), (b"data", &data)]);
+        let wav = Wav::parse(&src).unwrap();
+        assert_eq!(wav.frames(), 9);
+        // +7 grows the step each time, 0 shrinks it
+        assert_eq!(
+            wav.decode_i16().unwrap(),
+            [0, 11, 41, 104, 240, 259, 276, 292, 306]
+        );
+    }
+
+    #[test]
+    fn decodes_ms_adpcm() {
+        // 6 samples per block, no coefficients of its own so the standard ones
+        let format = fmt(WAVE_FORMAT_ADPCM, 1, 9, 4, &[6, 0, 0, 0]);
+        // predictor 0, delta 16, sample1 100, sample2 50
+        let data = [0, 16, 0, 100, 0, 50, 0, 0x1F, 0x70];
+        let src = riff(&[(b"fmt ", &format), (b"data", &data)]);
+        let wav = Wav::parse(&src).unwrap();
+        assert_eq!(wav.frames(), 6);
+        assert_eq!(wav.decode_i16().unwrap(), [50, 100, 116, 100, 212, 212]);
+    }
+
+    #[test]
+    fn reads_loops_and_cues() {
+        let format = fmt(WAVE_FORMAT_PCM, 1, 2, 16, &[]);
+        let data = [0; 16000];
+        let mut smpl = vec![0; 36];
+        le::write(&mut smpl, 28, 1u32);
+        for x in [7u32, 0, 100, 7000, 0, 0] {
+            le::push(&mut smpl, x);
+        }
+        let mut cue = Vec::new();
+        le::push(&mut cue, 1u32);
+        for x in [7u32, 100] {
+            le::push(&mut cue, x);
+        }
+        cue.extend_from_slice(b"data");
+        for x in [0u32, 0, 100] {
+            le::push(&mut cue, x);
+        }
+        let src = riff(&[
+            (b"fmt ", &format),
+            (b"cue ", &cue),
+            (b"data", &data),
+            (b"smpl", &smpl),
+        ]);
+        let wav = Wav::parse(&src).unwrap();
+        assert_eq!(
+            wav.loops,
+            [SampleLoop {
+                cue_id: 7,
+                typ: 0,
+                start: 100,
+                end: 7000,
+                fraction: 0,
+                play_count: 0,
+            }]
+        );
+        assert_eq!(
+            wav.cues,
+            [CuePoint {
+                id: 7,
+                position: 100,
+                chunk_id: *b"data",
+                chunk_start: 0,
+                block_start: 0,
+                sample_offset: 100,
+            }]
+        );
+        assert_eq!(wav.frames(), 8000);
+        assert_eq!(wav.duration(), std::time::Duration::from_secs(1));
+        validate(&src).unwrap();
+
+        // a loop past the end
+        le::write(&mut smpl, 48, 9000u32);
+        let src = riff(&[(b"fmt ", &format), (b"data", &data), (b"smpl", &smpl)]);
+        assert!(matches!(validate(&src), Err(SoundError::BadChunk("smpl"))));
+        // a cue past the end
+        le::write(&mut cue, 24, 9000u32);
+        let src = riff(&[(b"fmt ", &format), (b"data", &data), (b"cue ", &cue)]);
+        assert!(matches!(validate(&src), Err(SoundError::BadChunk("cue "))));
+    }
+
+    #[test]
+    fn frames_from_fact() {
+        let format = fmt(WAVE_FORMAT_IMA_ADPCM, 1, 8, 4, &[9, 0]);
+        let data = [0, 0, 0, 0, 0x77, 0x77, 0x00, 0x00];
+        let fact = 5u32.to_le_bytes();
+        let src = riff(&[(b"fmt ", &format), (b"fact", &fact), (b"data", &data)]);
+        let wav = Wav::parse(&src).unwrap();
+        assert_eq!(wav.frames(), 5);
+        assert_eq!(wav.decode_i16().unwrap(), [0, 11, 41, 104, 240]);
+        assert_eq!(wav.duration(), std::time::Duration::from_micros(625));
+    }
+
+    #[test]
+    fn validate_errors() {
+        // data says 100 bytes, there are 4
+        let mut src = pcm(1, 16, &[0; 4]);
+        let at = src.len() - 8;
+        le::write(&mut src, at, 100u32);
+        assert!(matches!(validate(&src), Err(SoundError::TooSmall(_))));
+
+        let format = fmt(WAVE_FORMAT_PCM, 1, 0, 16, &[]);
+        let src = riff(&[(b"fmt ", &format), (b"data", &[0; 4])]);
+        assert!(matches!(validate(&src), Err(SoundError::BadChunk("fmt "))));
+
+        for (channels, block_align) in [(0, 2), (2, 2)] {
+            let format = fmt(WAVE_FORMAT_PCM, channels, block_align, 16, &[]);
+            let src = riff(&[(b"fmt ", &format), (b"data", &[0; 4])]);
+            assert!(matches!(validate(&src), Err(SoundError::BadChunk("fmt "))));
+        }
+
+        let src = riff(&[(b"fmt ", &fmt(WAVE_FORMAT_PCM, 1, 2, 16, &[]))]);
+        assert!(matches!(
+            validate(&src),
+            Err(SoundError::MissingChunk("data"))
+        ));
+        assert!(matches!(validate(b"RIFX"), Err(SoundError::TooSmall(4))));
+        assert!(matches!(
+            validate(b"RIFF\0\0\0\0AVI "),
+            Err(SoundError::NotWave)
+        ));
+    }
+}
@@ -0,0 +1,122 @@
+use thiserror::Error;
+
+pub mod bmp;
+pub mod tga;
+
+#[derive(Error, Debug)]
+pub enum TextureError {
+    #[error("data too small, got {0} bytes")]
+    TooSmall(usize),
+    #[error("unsupported image type {0}")]
+    UnsupportedType(u32),
+    #[error("unsupported pixel depth {0}")]
+    UnsupportedDepth(u32),
+    #[error("colormap index {0} out of range")]
+    BadIndex(usize),
+    #[error("unreasonable image size {0}x{1}")]
+    BadDimensions(u32, u32),
+    #[error("image too large for the format: {0}x{1}")]
+    TooLarge(u32, u32),
+    #[error("re-encoded data differs at offset {0}")]
+    RoundtripMismatch(usize),
+    #[error("re-encoded pixel differs at {0},{1}")]
+    PixelMismatch(u32, u32),
+    #[cfg(feature = "png")]
+    #[error(transparent)]
+    Png(#[from] png::EncodingError),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+/// 8 bits per channel RGBA, rows go top to bottom.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RgbaImage {
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<u8>,
+}
+
+impl RgbaImage {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            data: vec![0; width as usize * height as usize * 4],
+        }
+    }
+
+    pub fn pixel(&self, x: u32, y: u32) -> [u8; 4] {
+        let i = (y as usize * self.width as usize + x as usize) * 4;
+        self.data[i..i + 4].try_into().unwrap()
+    }
+
+    pub fn set_pixel(&mut self, x: u32, y: u32, rgba: [u8; 4]) {
+        let i = (y as usize * self.width as usize + x as usize) * 4;
+        self.data[i..i + 4].copy_from_slice(&rgba);
+    }
+
+    #[cfg(feature = "png")]
+    pub fn write_png<W: std::io::Write>(&self, w: W) -> Result<(), TextureError> {
+        let mut encoder = png::Encoder::new(w, self.width, self.height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&self.data)?;
+        Ok(())
+    }
+}
+
+// 5 bit channel to 8 bits
+pub(crate) fn expand5(x: u16) -> u8 {
+    let x = (x & 0x1F) as u8;
+    (x << 3) | (x >> 2)
+}
+
+// Squared distance pick, for putting edited pixels back into a palette
+pub(crate) fn nearest(palette: &[[u8; 4]], rgba: [u8; 4]) -> usize {
+    palette
+        .iter()
+        .enumerate()
+        .min_by_key(|(_, x)| {
+            x.iter()
+                .zip(rgba)
+                .map(|(&a, b)| (a as i32 - b as i32).pow(2))
+                .sum::<i32>()
+        })
+        .map(|(i, _)| i)
+        .unwrap_or(0)
+}
+
+pub(crate) fn luminance(rgba: [u8; 4]) -> u8 {
+    ((rgba[0] as u32 * 77 + rgba[1] as u32 * 150 + rgba[2] as u32 * 29) >> 8) as u8
+}
+
+pub(crate) fn first_mismatch(a: &[u8], b: &[u8]) -> Option<usize> {
+    match a.iter().zip(b).position(|(x, y)| x != y) {
+        None if a.len() != b.len() => Some(a.len().min(b.len())),
+        x => x,
+    }
+}
+
+// Both untouched bytes and every pixel put through the encoder, which leaves unchanged pixels
+// alone: over a blanked copy of the file it can't copy anything.
+pub(crate) fn verify_roundtrip(
+    src: &[u8],
+    decode: fn(&[u8]) -> Result<RgbaImage, TextureError>,
+    encode: fn(&RgbaImage, &[u8]) -> Result<Vec<u8>, TextureError>,
+) -> Result<(), TextureError> {
+    let image = decode(src)?;
+    if let Some(i) = first_mismatch(&encode(&image, src)?, src) {
+        return Err(TextureError::RoundtripMismatch(i));
+    }
+    let blank = encode(&RgbaImage::new(image.width, image.height), src)?;
+    let decoded = decode(&encode(&image, &blank)?)?;
+    for y in 0..image.height {
+        for x in 0..image.width {
+            if decoded.pixel(x, y) != image.pixel(x, y) {
+                return Err(TextureError::PixelMismatch(x, y));
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,851 @@
+use super::{nearest, RgbaImage, TextureError};
+use crate::le;
+
+pub const FILE_HEADER_SIZE: usize = 14;
+// BITMAPCOREHEADER, the OS/2 one with 16 bit sizes
+pub const CORE_HEADER_SIZE: u32 = 12;
+
+pub const BI_RGB: u32 = 0;
+pub const BI_RLE8: u32 = 1;
+pub const BI_RLE4: u32 = 2;
+pub const BI_BITFIELDS: u32 = 3;
+pub const BI_ALPHABITFIELDS: u32 = 6;
+
+// Far beyond any texture the game has, keeps a bad header from allocating gigabytes
+pub const MAX_PIXELS: u64 = 1 << 26;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BmpHeader {
+    pub file_size: u32,
+    pub data_offset: u32,
+    pub info_size: u32,
+    pub width: i32,
+    // negative means rows go top to bottom
+    pub height: i32,
+    pub planes: u16,
+    pub depth: u16,
+    pub compression: u32,
+    pub image_size: u32,
+    pub x_ppm: i32,
+    pub y_ppm: i32,
+    pub colors_used: u32,
+    pub colors_important: u32,
+    // R G B A
+    pub masks: [u32; 4],
+}
+
+impl BmpHeader {
+    pub fn read(src: &[u8]) -> Result<Self, TextureError> {
+        if src.len() < FILE_HEADER_SIZE + CORE_HEADER_SIZE as usize || &src[..2] != b"BM" {
+            return Err(TextureError::TooSmall(src.len()));
+        }
+        let u16_at = |i: usize| le::read::<u16>(src, i);
+        let u32_at = |i: usize| le::read::<u32>(src, i);
+        let info_size = u32_at(14);
+        let mut ret = Self {
+            file_size: u32_at(2),
+            data_offset: u32_at(10),
+            info_size,
+            width: 0,
+            height: 0,
+            planes: 0,
+            depth: 0,
+            compression: BI_RGB,
+            image_size: 0,
+            x_ppm: 0,
+            y_ppm: 0,
+            colors_used: 0,
+            colors_important: 0,
+            masks: [0; 4],
+        };
+        if info_size == CORE_HEADER_SIZE {
+            ret.width = u16_at(18) as i32;
+            ret.height = u16_at(20) as i32;
+            ret.planes = u16_at(22);
+            ret.depth = u16_at(24);
+        } else {
+            if info_size < 40 || src.len() < FILE_HEADER_SIZE + info_size as usize {
+                return Err(TextureError::TooSmall(src.len()));
+            }
+            ret.width = u32_at(18) as i32;
+            ret.height = u32_at(22) as i32;
+            ret.planes = u16_at(26);
+            ret.depth = u16_at(28);
+            ret.compression = u32_at(30);
+            ret.image_size = u32_at(34);
+            ret.x_ppm = u32_at(38) as i32;
+            ret.y_ppm = u32_at(42) as i32;
+            ret.colors_used = u32_at(46);
+            ret.colors_important = u32_at(50);
+        }
+
+        ret.masks = match (ret.compression, ret.depth) {
+            (BI_BITFIELDS | BI_ALPHABITFIELDS, _) => {
+                // V4/V5 headers have them inside, plain info headers right after
+                let at = FILE_HEADER_SIZE + 40;
+                let count = if ret.compression == BI_ALPHABITFIELDS || info_size >= 56 {
+                    4
+                } else {
+                    3
+                };
+                if src.len() < at + count * 4 {
+                    return Err(TextureError::TooSmall(src.len()));
+                }
+                let mut masks = [0; 4];
+                for (i, mask) in masks.iter_mut().enumerate().take(count) {
+                    *mask = u32_at(at + i * 4);
+                }
+                masks
+            }
+            (_, 16) => [0x7C00, 0x3E0, 0x1F, 0],
+            (_, 24 | 32) => [0xFF0000, 0xFF00, 0xFF, 0],
+            _ => [0; 4],
+        };
+        Ok(ret)
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width.unsigned_abs()
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height.unsigned_abs()
+    }
+
+    pub fn top_to_bottom(&self) -> bool {
+        self.height < 0
+    }
+
+    pub fn is_core(&self) -> bool {
+        self.info_size == CORE_HEADER_SIZE
+    }
+
+    // Bytes in one palette entry, core headers don't have the padding byte
+    pub fn palette_entry_size(&self) -> usize {
+        if self.is_core() {
+            3
+        } else {
+            4
+        }
+    }
+
+    pub fn palette_offset(&self) -> usize {
+        let masks = match self.compression {
+            BI_BITFIELDS if self.info_size == 40 => 12,
+            BI_ALPHABITFIELDS if self.info_size == 40 => 16,
+            _ => 0,
+        };
+        FILE_HEADER_SIZE + self.info_size as usize + masks
+    }
+
+    pub fn palette_len(&self) -> usize {
+        match (self.colors_used, self.depth) {
+            (0, 1 | 4 | 8) => 1 << self.depth,
+            (n, _) => n as usize,
+        }
+    }
+
+    // Rows are padded to 4 bytes
+    pub fn row_size(&self) -> usize {
+        (self.width() as usize * self.depth as usize).div_ceil(32) * 4
+    }
+
+    pub fn is_rle(&self) -> bool {
+        matches!(self.compression, BI_RLE8 | BI_RLE4)
+    }
+
+    fn check(&self) -> Result<(), TextureError> {
+        if !matches!(
+            self.compression,
+            BI_RGB | BI_RLE8 | BI_RLE4 | BI_BITFIELDS | BI_ALPHABITFIELDS
+        ) {
+            return Err(TextureError::UnsupportedType(self.compression));
+        }
+        if !matches!(self.depth, 1 | 4 | 8 | 16 | 24 | 32) {
+            return Err(TextureError::UnsupportedDepth(self.depth as u32));
+        }
+        let (width, height) = (self.width(), self.height());
+        if width == 0 || height == 0 || width as u64 * height as u64 > MAX_PIXELS {
+            return Err(TextureError::BadDimensions(width, height));
+        }
+        Ok(())
+    }
+}
+
+pub fn read_palette(header: &BmpHeader, src: &[u8]) -> Result<Vec<[u8; 4]>, TextureError> {
+    let size = header.palette_entry_size();
+    let start = header.palette_offset();
+    // some writers claim more colors than there's room for before the pixels
+    let end = (start + header.palette_len() * size).min(header.data_offset as usize);
+    let data = src
+        .get(start..end.max(start))
+        .ok_or(TextureError::TooSmall(src.len()))?;
+    Ok(data
+        .chunks_exact(size)
+        .map(|x| [x[2], x[1], x[0], 255])
+        .collect())
+}
+
+// Indexed pixels are packed from the high bits down
+fn get_index(row: &[u8], x: usize, depth: u16) -> u8 {
+    match depth {
+        1 => (row[x / 8] >> (7 - x % 8)) & 1,
+        4 => (row[x / 2] >> (4 * (1 - x % 2))) & 0xF,
+        _ => row[x],
+    }
+}
+
+fn set_index(row: &mut [u8], x: usize, depth: u16, index: u8) {
+    match depth {
+        1 => {
+            let shift = 7 - x % 8;
+            row[x / 8] = (row[x / 8] & !(1 << shift)) | ((index & 1) << shift);
+        }
+        4 => {
+            let shift = 4 * (1 - x % 2);
+            row[x / 2] = (row[x / 2] & !(0xF << shift)) | ((index & 0xF) << shift);
+        }
+        _ => row[x] = index,
+    }
+}
+
+// Scales the masked bits to 8 bits, empty masks mean opaque/absent
+fn channel(v: u32, mask: u32, default: u8) -> u8 {
+    if mask == 0 {
+        return default;
+    }
+    let bits = mask.count_ones();
+    let max = (1u64 << bits) - 1;
+    let x = ((v & mask) >> mask.trailing_zeros()) as u64;
+    (x * 255 / max) as u8
+}
+
+// Inverse of `channel`
+fn pack(value: u8, mask: u32) -> u32 {
+    if mask == 0 {
+        return 0;
+    }
+    let max = (1u64 << mask.count_ones()) - 1;
+    let x = (value as u64 * max + 127) / 255;
+    (x as u32) << mask.trailing_zeros()
+}
+
+/// Palette indices for RLE4/RLE8 in file row order, skipped pixels are 0.
+pub fn read_rle(header: &BmpHeader, src: &[u8]) -> Result<Vec<u8>, TextureError> {
+    unpack_rle(header, src).map(|(indices, _)| indices)
+}
+
+// Also returns where the pixel data ends
+fn unpack_rle(header: &BmpHeader, src: &[u8]) -> Result<(Vec<u8>, usize), TextureError> {
+    header.check()?;
+    let width = header.width() as usize;
+    let height = header.height() as usize;
+    let mut ret = vec![0u8; width * height];
+    let data = src
+        .get(header.data_offset as usize..)
+        .ok_or(TextureError::TooSmall(src.len()))?;
+    let too_small = || TextureError::TooSmall(src.len());
+    let rle4 = header.compression == BI_RLE4;
+
+    let (mut x, mut y) = (0usize, 0usize);
+    let mut put = |x: &mut usize, y: usize, index: u8| {
+        if *x < width && y < height {
+            ret[y * width + *x] = index;
+        }
+        *x += 1;
+    };
+    let mut cursor = 0;
+    loop {
+        let count = *data.get(cursor).ok_or_else(too_small)? as usize;
+        let value = *data.get(cursor + 1).ok_or_else(too_small)?;
+        cursor += 2;
+        if count > 0 {
+            for i in 0..count {
+                let index = if rle4 {
+                    if i & 1 == 0 {
+                        value >> 4
+                    } else {
+                        value & 0xF
+                    }
+                } else {
+                    value
+                };
+                put(&mut x, y, index);
+            }
+            continue;
+        }
+        match value {
+            // end of line
+            0 => {
+                x = 0;
+                y += 1;
+            }
+            // end of bitmap
+            1 => break,
+            // delta
+            2 => {
+                x += *data.get(cursor).ok_or_else(too_small)? as usize;
+                y += *data.get(cursor + 1).ok_or_else(too_small)? as usize;
+                cursor += 2;
+            }
+            // absolute run, padded to 2 bytes
+            n => {
+                let n = n as usize;
+                let len = if rle4 { n.div_ceil(2) } else { n };
+                let run = data.get(cursor..cursor + len).ok_or_else(too_small)?;
+                for i in 0..n {
+                    let index = if rle4 {
+                        let b = run[i / 2];
+                        if i & 1 == 0 {
+                            b >> 4
+                        } else {
+                            b & 0xF
+                        }
+                    } else {
+                        run[i]
+                    };
+                    put(&mut x, y, index);
+                }
+                cursor += len + (len & 1);
+            }
+        }
+        if y >= height {
+            break;
+        }
+    }
+    Ok((ret, header.data_offset as usize + cursor))
+}
+
+pub fn decode(src: &[u8]) -> Result<RgbaImage, TextureError> {
+    let header = BmpHeader::read(src)?;
+    header.check()?;
+    let width = header.width();
+    let height = header.height();
+    let palette = read_palette(&header, src)?;
+    let lookup = |index: u8| {
+        palette
+            .get(index as usize)
+            .copied()
+            .ok_or(TextureError::BadIndex(index as usize))
+    };
+    // uncompressed pixels have to be there before anything gets allocated for them
+    let row_size = header.row_size();
+    let start = header.data_offset as usize;
+    let data = if header.is_rle() {
+        &[][..]
+    } else {
+        src.get(start..start + row_size * height as usize)
+            .ok_or(TextureError::TooSmall(src.len()))?
+    };
+    let mut ret = RgbaImage::new(width, height);
+    let dest_row = |y: u32| {
+        if header.top_to_bottom() {
+            y
+        } else {
+            height - 1 - y
+        }
+    };
+
+    if header.is_rle() {
+        let indices = read_rle(&header, src)?;
+        for y in 0..height {
+            for x in 0..width {
+                let index = indices[(y * width + x) as usize];
+                ret.set_pixel(x, dest_row(y), lookup(index)?);
+            }
+        }
+        return Ok(ret);
+    }
+
+    let [r_mask, g_mask, b_mask, a_mask] = header.masks;
+    for (y, row) in data.chunks_exact(row_size).enumerate() {
+        let y = dest_row(y as u32);
+        for x in 0..width {
+            let i = x as usize;
+            let rgba = match header.depth {
+                1 | 4 | 8 => lookup(get_index(row, i, header.depth))?,
+                depth => {
+                    let size = depth as usize / 8;
+                    let mut bytes = [0u8; 4];
+                    bytes[..size].copy_from_slice(&row[i * size..i * size + size]);
+                    let v = le::read::<u32>(&bytes, 0);
+                    [
+                        channel(v, r_mask, 0),
+                        channel(v, g_mask, 0),
+                        channel(v, b_mask, 0),
+                        channel(v, a_mask, 255),
+                    ]
+                }
+            };
+            ret.set_pixel(x, y, rgba);
+        }
+    }
+    Ok(ret)
+}
+
+// Same shape GDI writes: runs of 2+, absolute runs for 3+ literals, EOL per row and EOF last
+fn rle_encode(indices: &[u8], width: usize, rle4: bool, out: &mut Vec<u8>) {
+    let rows = indices.chunks(width.max(1)).count();
+    for (y, row) in indices.chunks(width.max(1)).enumerate() {
+        let mut i = 0;
+        while i < row.len() {
+            let mut run = 1;
+            while i + run < row.len() && run < 255 && row[i + run] == row[i] {
+                run += 1;
+            }
+            if run > 1 {
+                let value = if rle4 { row[i] << 4 | row[i] } else { row[i] };
+                out.extend_from_slice(&[run as u8, value]);
+                i += run;
+                continue;
+            }
+            let start = i;
+            while i < row.len() && i - start < 255 && !(i + 1 < row.len() && row[i + 1] == row[i]) {
+                i += 1;
+            }
+            let literal = &row[start..i];
+            if literal.len() < 3 {
+                for &x in literal {
+                    let value = if rle4 { x << 4 } else { x };
+                    out.extend_from_slice(&[1, value]);
+                }
+                continue;
+            }
+            out.extend_from_slice(&[0, literal.len() as u8]);
+            let len = if rle4 {
+                for pair in literal.chunks(2) {
+                    out.push(pair[0] << 4 | pair.get(1).copied().unwrap_or(0));
+                }
+                literal.len().div_ceil(2)
+            } else {
+                out.extend_from_slice(literal);
+                literal.len()
+            };
+            if len & 1 != 0 {
+                out.push(0);
+            }
+        }
+        if y + 1 < rows {
+            out.extend_from_slice(&[0, 0]);
+        }
+    }
+    out.extend_from_slice(&[0, 1]);
+}
+
+/// Encodes `image` in the exact format of `original`, keeping its headers, palette and orientation.
+/// Pixels that didn't change keep their original bits.
+pub fn encode(image: &RgbaImage, original: &[u8]) -> Result<Vec<u8>, TextureError> {
+    // decode wouldn't take the result
+    if image.width == 0
+        || image.height == 0
+        || image.width as u64 * image.height as u64 > MAX_PIXELS
+    {
+        return Err(TextureError::BadDimensions(image.width, image.height));
+    }
+    let header = BmpHeader::read(original)?;
+    header.check()?;
+    let old_image = decode(original)?;
+    let palette = read_palette(&header, original)?;
+    let same_size = old_image.width == image.width && old_image.height == image.height;
+    let limit = if header.is_core() {
+        u16::MAX as u32
+    } else {
+        i32::MAX as u32
+    };
+    if image.width > limit || image.height > limit {
+        return Err(TextureError::TooLarge(image.width, image.height));
+    }
+
+    let width = image.width as usize;
+    let height = image.height as usize;
+    let depth = header.depth;
+    let data_offset = header.data_offset as usize;
+    let mut ret = original
+        .get(..data_offset)
+        .ok_or(TextureError::TooSmall(original.len()))?
+        .to_vec();
+    if header.is_core() {
+        le::write(&mut ret, 18, width as u16);
+        le::write(&mut ret, 20, height as u16);
+    } else {
+        let signed_height = if header.top_to_bottom() {
+            -(height as i32)
+        } else {
+            height as i32
+        };
+        le::write(&mut ret, 18, width as i32);
+        le::write(&mut ret, 22, signed_height);
+    }
+    // file row to image row
+    let image_row = |y: usize| {
+        if header.top_to_bottom() {
+            y
+        } else {
+            height - 1 - y
+        }
+    };
+
+    let end = if header.is_rle() {
+        let (old_indices, end) = unpack_rle(&header, original)?;
+        let mut indices = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                let rgba = image.pixel(x as u32, image_row(y) as u32);
+                if same_size && old_image.pixel(x as u32, image_row(y) as u32) == rgba {
+                    indices.push(old_indices[y * width + x]);
+                } else {
+                    indices.push(nearest(&palette, rgba) as u8);
+                }
+            }
+        }
+        rle_encode(&indices, width, header.compression == BI_RLE4, &mut ret);
+        end
+    } else {
+        let old_row_size = header.row_size();
+        let row_size = (width * depth as usize).div_ceil(32) * 4;
+        let old_data = original
+            .get(data_offset..data_offset + old_row_size * old_image.height as usize)
+            .ok_or(TextureError::TooSmall(original.len()))?;
+        let [r_mask, g_mask, b_mask, a_mask] = header.masks;
+        for y in 0..height {
+            let mut row = vec![0u8; row_size];
+            let old_row = &old_data[y.min(old_image.height as usize) * old_row_size..];
+            for x in 0..width {
+                let rgba = image.pixel(x as u32, image_row(y) as u32);
+                let unchanged = same_size && old_image.pixel(x as u32, image_row(y) as u32) == rgba;
+                match depth {
+                    1 | 4 | 8 => {
+                        let index = if unchanged {
+                            get_index(old_row, x, depth)
+                        } else {
+                            nearest(&palette, rgba) as u8
+                        };
+                        set_index(&mut row, x, depth, index);
+                    }
+                    _ => {
+                        let size = depth as usize / 8;
+                        let bytes = &mut row[x * size..x * size + size];
+                        if unchanged {
+                            bytes.copy_from_slice(&old_row[x * size..x * size + size]);
+                        } else {
+                            let [r, g, b, a] = rgba;
+                            let v = pack(r, r_mask)
+                                | pack(g, g_mask)
+                                | pack(b, b_mask)
+                                | pack(a, a_mask);
+                            bytes.copy_from_slice(&v.to_le_bytes()[..size]);
+                        }
+                    }
+                }
+            }
+            ret.extend_from_slice(&row);
+        }
+        data_offset + old_data.len()
+    };
+
+    let pixels_len = ret.len() - data_offset;
+    ret.extend_from_slice(&original[end.min(original.len())..]);
+    // only touch the sizes when the original had them right
+    if header.file_size as usize == original.len() {
+        let len = ret.len() as u32;
+        le::write(&mut ret, 2, len);
+    }
+    if !header.is_core() && header.image_size as usize == end - data_offset {
+        le::write(&mut ret, 34, pixels_len as u32);
+    }
+    Ok(ret)
+}
+
+/// Checks that decoding and encoding an untouched file gives back the same bytes, and that
+/// every pixel survives the encoder when it can't just copy the original bytes.
+pub fn verify_roundtrip(src: &[u8]) -> Result<(), TextureError> {
+    super::verify_roundtrip(src, decode, encode)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 24 bit BI_RGB header with whatever size, followed by `data`
+    fn bmp(width: i32, height: i32, data: &[u8]) -> Vec<u8> {
+        let mut ret = b"BM".to_vec();
+        le::push(&mut ret, (54 + data.len()) as u32);
+        le::push(&mut ret, 0u32);
+        le::push(&mut ret, 54u32);
+        le::push(&mut ret, 40u32);
+        le::push(&mut ret, width);
+        le::push(&mut ret, height);
+        le::push(&mut ret, 1u16);
+        le::push(&mut ret, 24u16);
+        ret.resize(54, 0);
+        ret.extend_from_slice(data);
+        ret
+    }
+
+    #[test]
+    fn decodes_small_image() {
+        // bottom to top, rows padded to 8 bytes
+        let data = [0, 0, 255, 0, 255, 0, 0, 0, 255, 0, 0, 1, 2, 3, 0, 0];
+        let image = decode(&bmp(2, 2, &data)).unwrap();
+        assert_eq!(image.pixel(0, 1), [255, 0, 0, 255]);
+        assert_eq!(image.pixel(1, 1), [0, 255, 0, 255]);
+        assert_eq!(image.pixel(0, 0), [0, 0, 255, 255]);
+        assert_eq!(image.pixel(1, 0), [3, 2, 1, 255]);
+    }
+
+    // 16x16 32 bit BI_BITFIELDS with a V5 header, from CPython's test data
+    const PYTHON: &[u8] =
+        include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/python.bmp"));
+
+    #[test]
+    fn fixture_roundtrips() {
+        verify_roundtrip(PYTHON).unwrap();
+        let image = decode(PYTHON).unwrap();
+        let blank = encode(&RgbaImage::new(image.width, image.height), PYTHON).unwrap();
+        assert_ne!(blank, PYTHON);
+        assert_eq!(encode(&image, &blank).unwrap(), PYTHON);
+    }
+
+    #[test]
+    fn crafted_headers_are_rejected() {
+        for (width, height) in [(0, 1), (1, 0), (0, 0), (1, i32::MIN), (i32::MIN, 1)] {
+            assert!(matches!(
+                decode(&bmp(width, height, &[0; 16])),
+                Err(TextureError::BadDimensions(..))
+            ));
+        }
+        // allowed sizes, but nowhere near enough pixels
+        for (width, height) in [(4096, -4096), (1, 1 << 20), (8192, 8192)] {
+            assert!(matches!(
+                decode(&bmp(width, height, &[0; 16])),
+                Err(TextureError::TooSmall(_))
+            ));
+        }
+        let mut rle = bmp(0, 1, &[0, 1]);
+        rle[28] = 8;
+        rle[30] = BI_RLE8 as u8;
+        let header = BmpHeader::read(&rle).unwrap();
+        assert!(read_rle(&header, &rle).is_err());
+    }
+
+    // Any header: 40 byte info or 12 byte core, `extra` is the masks and palette
+    fn build(
+        core: bool,
+        (width, height): (i32, i32),
+        depth: u16,
+        compression: u32,
+        extra: &[u8],
+        data: &[u8],
+    ) -> Vec<u8> {
+        let info_size = if core { CORE_HEADER_SIZE } else { 40 };
+        let data_offset = FILE_HEADER_SIZE + info_size as usize + extra.len();
+        let mut ret = b"BM".to_vec();
+        le::push(&mut ret, (data_offset + data.len()) as u32);
+        le::push(&mut ret, 0u32);
+        le::push(&mut ret, data_offset as u32);
+        le::push(&mut ret, info_size);
+        if core {
+            le::push(&mut ret, width as u16);
+            le::push(&mut ret, height as u16);
+            le::push(&mut ret, 1u16);
+            le::push(&mut ret, depth);
+        } else {
+            le::push(&mut ret, width);
+            le::push(&mut ret, height);
+            le::push(&mut ret, 1u16);
+            le::push(&mut ret, depth);
+            le::push(&mut ret, compression);
+            ret.resize(FILE_HEADER_SIZE + 40, 0);
+        }
+        ret.extend_from_slice(extra);
+        ret.extend_from_slice(data);
+        ret
+    }
+
+    // Index i is (30i, 20i, 10i), stored as BGR with padding unless it's for a core header
+    fn palette(len: u8, core: bool) -> Vec<u8> {
+        let mut ret = Vec::new();
+        for i in 0..len {
+            ret.extend_from_slice(&[10 * i, 20 * i, 30 * i]);
+            if !core {
+                ret.push(0);
+            }
+        }
+        ret
+    }
+
+    fn color(i: u8) -> [u8; 4] {
+        [30 * i, 20 * i, 10 * i, 255]
+    }
+
+    // Palette indices of the decoded image, top row first
+    fn indices(image: &RgbaImage, len: u8) -> Vec<Vec<u8>> {
+        (0..image.height)
+            .map(|y| {
+                (0..image.width)
+                    .map(|x| {
+                        let rgba = image.pixel(x, y);
+                        (0..len).find(|&i| color(i) == rgba).unwrap()
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn decodes_paletted() {
+        // bottom row first, high bits first
+        let src = build(
+            false,
+            (3, 2),
+            1,
+            BI_RGB,
+            &palette(2, false),
+            &[0b1010_0000, 0, 0, 0, 0b0110_0000, 0, 0, 0],
+        );
+        let image = decode(&src).unwrap();
+        assert_eq!(indices(&image, 2), [[0, 1, 1], [1, 0, 1]]);
+        verify_roundtrip(&src).unwrap();
+
+        let src = build(
+            false,
+            (3, 1),
+            4,
+            BI_RGB,
+            &palette(3, false),
+            &[0x20, 0x10, 0, 0],
+        );
+        assert_eq!(indices(&decode(&src).unwrap(), 3), [[2, 0, 1]]);
+        verify_roundtrip(&src).unwrap();
+
+        // top row first
+        let src = build(
+            false,
+            (2, -2),
+            8,
+            BI_RGB,
+            &palette(2, false),
+            &[1, 0, 0, 0, 0, 1, 0, 0],
+        );
+        assert_eq!(indices(&decode(&src).unwrap(), 2), [[1, 0], [0, 1]]);
+        verify_roundtrip(&src).unwrap();
+
+        // an index past the palette
+        let src = build(false, (1, 1), 8, BI_RGB, &palette(2, false), &[5, 0, 0, 0]);
+        assert!(matches!(decode(&src), Err(TextureError::BadIndex(5))));
+    }
+
+    #[test]
+    fn decodes_16_bit() {
+        // 555 without masks
+        let src = build(
+            false,
+            (4, 1),
+            16,
+            BI_RGB,
+            &[],
+            &[0x00, 0x7C, 0xE0, 0x03, 0x1F, 0x00, 0x10, 0x42],
+        );
+        let image = decode(&src).unwrap();
+        assert_eq!(image.pixel(0, 0), [255, 0, 0, 255]);
+        assert_eq!(image.pixel(1, 0), [0, 255, 0, 255]);
+        assert_eq!(image.pixel(2, 0), [0, 0, 255, 255]);
+        assert_eq!(image.pixel(3, 0), [131, 131, 131, 255]);
+        verify_roundtrip(&src).unwrap();
+
+        // 565 with the masks after the info header
+        let mut masks = Vec::new();
+        le::push_all(&mut masks, &[0xF800u32, 0x7E0, 0x1F]);
+        let src = build(
+            false,
+            (3, 1),
+            16,
+            BI_BITFIELDS,
+            &masks,
+            &[0x00, 0xF8, 0xE0, 0x07, 0x10, 0x04, 0, 0],
+        );
+        assert_eq!(
+            BmpHeader::read(&src).unwrap().masks,
+            [0xF800, 0x7E0, 0x1F, 0]
+        );
+        let image = decode(&src).unwrap();
+        assert_eq!(image.pixel(0, 0), [255, 0, 0, 255]);
+        assert_eq!(image.pixel(1, 0), [0, 255, 0, 255]);
+        assert_eq!(image.pixel(2, 0), [0, 129, 131, 255]);
+        verify_roundtrip(&src).unwrap();
+    }
+
+    #[test]
+    fn decodes_core_header() {
+        let src = build(
+            true,
+            (3, 2),
+            8,
+            BI_RGB,
+            &palette(3, true),
+            &[0, 1, 2, 0, 2, 1, 0, 0],
+        );
+        let header = BmpHeader::read(&src).unwrap();
+        assert!(header.is_core());
+        assert_eq!((header.width(), header.height()), (3, 2));
+        assert_eq!(indices(&decode(&src).unwrap(), 3), [[2, 1, 0], [0, 1, 2]]);
+        verify_roundtrip(&src).unwrap();
+
+        let src = build(true, (1, 1), 24, BI_RGB, &[], &[1, 2, 3, 0]);
+        assert_eq!(decode(&src).unwrap().pixel(0, 0), [3, 2, 1, 255]);
+    }
+
+    #[test]
+    fn decodes_rle8() {
+        let data = [
+            2, 1, // two 1s
+            0, 2, 1, 1, // delta one right and one up, to the end of the next row
+            1, 2, // a 2
+            0, 0, // end of line
+            0, 3, 3, 0, 1, 0, // absolute 3 0 1, padded
+            0, 1, // end of bitmap
+        ];
+        let src = build(false, (4, 3), 8, BI_RLE8, &palette(4, false), &data);
+        let header = BmpHeader::read(&src).unwrap();
+        assert_eq!(
+            read_rle(&header, &src).unwrap(),
+            [1, 1, 0, 0, 0, 0, 0, 2, 3, 0, 1, 0]
+        );
+        assert_eq!(
+            indices(&decode(&src).unwrap(), 4),
+            [[3, 0, 1, 0], [0, 0, 0, 2], [1, 1, 0, 0]]
+        );
+        // re-encoded without the delta, the pixels are what matters
+        let image = decode(&src).unwrap();
+        assert_eq!(decode(&encode(&image, &src).unwrap()).unwrap(), image);
+    }
+
+    #[test]
+    fn decodes_rle4() {
+        let data = [
+            5, 0x12, // 1 2 1 2 1
+            0, 0, // end of line
+            0, 3, 0x34, 0x50, // absolute 3 4 5, two bytes so no padding
+            0, 2, 1, 0, // delta one right
+            1, 0x60, // a 6
+            0, 1, // end of bitmap
+        ];
+        let src = build(false, (5, 2), 4, BI_RLE4, &palette(7, false), &data);
+        assert_eq!(
+            indices(&decode(&src).unwrap(), 7),
+            [[3, 4, 5, 0, 6], [1, 2, 1, 2, 1]]
+        );
+        let image = decode(&src).unwrap();
+        assert_eq!(decode(&encode(&image, &src).unwrap()).unwrap(), image);
+    }
+
+    #[test]
+    fn encode_rejects_what_decode_would() {
+        for (width, height) in [(0, 0), (0, 16), (16, 0)] {
+            assert!(matches!(
+                encode(&RgbaImage::new(width, height), PYTHON),
+                Err(TextureError::BadDimensions(..))
+            ));
+        }
+    }
+}
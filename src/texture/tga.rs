@@ -0,0 +1,449 @@
+use super::{expand5, luminance, nearest, RgbaImage, TextureError};
+use crate::le;
+
+pub const HEADER_SIZE: usize = 18;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TgaHeader {
+    pub id_len: u8,
+    pub cmap_type: u8,
+    pub image_type: u8,
+    pub cmap_first: u16,
+    pub cmap_len: u16,
+    pub cmap_depth: u8,
+    pub x_origin: u16,
+    pub y_origin: u16,
+    pub width: u16,
+    pub height: u16,
+    pub depth: u8,
+    // bits 0-3 alpha bits, 4 right to left, 5 top to bottom
+    pub descriptor: u8,
+}
+
+impl TgaHeader {
+    pub fn read(src: &[u8]) -> Result<Self, TextureError> {
+        if src.len() < HEADER_SIZE {
+            return Err(TextureError::TooSmall(src.len()));
+        }
+        let u16_at = |i: usize| le::read::<u16>(src, i);
+        Ok(Self {
+            id_len: src[0],
+            cmap_type: src[1],
+            image_type: src[2],
+            cmap_first: u16_at(3),
+            cmap_len: u16_at(5),
+            cmap_depth: src[7],
+            x_origin: u16_at(8),
+            y_origin: u16_at(10),
+            width: u16_at(12),
+            height: u16_at(14),
+            depth: src[16],
+            descriptor: src[17],
+        })
+    }
+
+    pub fn is_rle(&self) -> bool {
+        self.image_type & 8 != 0
+    }
+
+    pub fn is_indexed(&self) -> bool {
+        self.image_type & 7 == 1
+    }
+
+    pub fn is_greyscale(&self) -> bool {
+        self.image_type & 7 == 3
+    }
+
+    pub fn alpha_bits(&self) -> u8 {
+        self.descriptor & 0xF
+    }
+
+    pub fn right_to_left(&self) -> bool {
+        self.descriptor & 0x10 != 0
+    }
+
+    pub fn top_to_bottom(&self) -> bool {
+        self.descriptor & 0x20 != 0
+    }
+
+    pub fn pixel_size(&self) -> usize {
+        (self.depth as usize).div_ceil(8)
+    }
+
+    pub fn cmap_entry_size(&self) -> usize {
+        (self.cmap_depth as usize).div_ceil(8)
+    }
+
+    // Where the colormap and pixels start
+    pub fn cmap_offset(&self) -> usize {
+        HEADER_SIZE + self.id_len as usize
+    }
+
+    pub fn data_offset(&self) -> usize {
+        self.cmap_offset() + self.cmap_len as usize * self.cmap_entry_size()
+    }
+
+    /// Where the `i`th pixel in file order ends up in a top to bottom image.
+    pub fn position(&self, i: usize) -> (u32, u32) {
+        let width = self.width as usize;
+        let (mut x, mut y) = (i % width, i / width);
+        if self.right_to_left() {
+            x = width - 1 - x;
+        }
+        if !self.top_to_bottom() {
+            y = self.height as usize - 1 - y;
+        }
+        (x as u32, y as u32)
+    }
+
+    fn check(&self) -> Result<(), TextureError> {
+        if !matches!(self.image_type, 1 | 2 | 3 | 9 | 10 | 11) {
+            return Err(TextureError::UnsupportedType(self.image_type as u32));
+        }
+        if !matches!(self.depth, 8 | 15 | 16 | 24 | 32) {
+            return Err(TextureError::UnsupportedDepth(self.depth as u32));
+        }
+        Ok(())
+    }
+}
+
+// BGR(A) or A1R5G5B5 true color value, `alpha` is whether the top bit of a 16 bit one counts
+fn color(src: &[u8], alpha: bool) -> Result<[u8; 4], TextureError> {
+    match src.len() {
+        2 => {
+            let v = le::read::<u16>(src, 0);
+            let a = if !alpha || v & 0x8000 != 0 { 255 } else { 0 };
+            Ok([expand5(v >> 10), expand5(v >> 5), expand5(v), a])
+        }
+        3 => Ok([src[2], src[1], src[0], 255]),
+        4 => Ok([src[2], src[1], src[0], src[3]]),
+        x => Err(TextureError::UnsupportedDepth(x as u32 * 8)),
+    }
+}
+
+/// Raw pixel values in file order with RLE undone.
+pub fn read_pixels(header: &TgaHeader, src: &[u8]) -> Result<Vec<u8>, TextureError> {
+    unpack(header, src).map(|(pixels, _)| pixels)
+}
+
+// Also returns where the pixel data ends
+fn unpack(header: &TgaHeader, src: &[u8]) -> Result<(Vec<u8>, usize), TextureError> {
+    let size = header.pixel_size();
+    let total = header.width as usize * header.height as usize * size;
+    let data = src
+        .get(header.data_offset()..)
+        .ok_or(TextureError::TooSmall(src.len()))?;
+    if !header.is_rle() {
+        return data
+            .get(..total)
+            .map(|x| (x.to_vec(), header.data_offset() + total))
+            .ok_or(TextureError::TooSmall(src.len()));
+    }
+
+    // a packet is at least 1 + size bytes for at most 128 pixels, don't trust the header further
+    if total / 128 > data.len() {
+        return Err(TextureError::TooSmall(src.len()));
+    }
+    let mut ret = Vec::with_capacity(total);
+    let mut cursor = 0;
+    while ret.len() < total {
+        let packet = *data.get(cursor).ok_or(TextureError::TooSmall(src.len()))?;
+        let count = (packet & 0x7F) as usize + 1;
+        let len = if packet & 0x80 != 0 {
+            size
+        } else {
+            size * count
+        };
+        let pixels = data
+            .get(cursor + 1..cursor + 1 + len)
+            .ok_or(TextureError::TooSmall(src.len()))?;
+        if packet & 0x80 != 0 {
+            for _ in 0..count {
+                ret.extend_from_slice(pixels);
+            }
+        } else {
+            ret.extend_from_slice(pixels);
+        }
+        cursor += 1 + len;
+    }
+    // packets are allowed to run over the last pixel
+    ret.truncate(total);
+    Ok((ret, header.data_offset() + cursor))
+}
+
+pub fn read_colormap(header: &TgaHeader, src: &[u8]) -> Result<Vec<[u8; 4]>, TextureError> {
+    let size = header.cmap_entry_size();
+    let data = src
+        .get(header.cmap_offset()..header.data_offset())
+        .ok_or(TextureError::TooSmall(src.len()))?;
+    if size == 0 {
+        return Ok(Vec::new());
+    }
+    // 15 bit entries never have alpha, 16 bit ones only if the descriptor says so
+    let alpha = header.cmap_depth == 16 && header.alpha_bits() != 0;
+    data.chunks_exact(size).map(|x| color(x, alpha)).collect()
+}
+
+pub fn decode(src: &[u8]) -> Result<RgbaImage, TextureError> {
+    let header = TgaHeader::read(src)?;
+    header.check()?;
+    let size = header.pixel_size();
+    let alpha = header.alpha_bits() != 0 && header.depth != 15;
+    let pixels = read_pixels(&header, src)?;
+    let colormap = read_colormap(&header, src)?;
+
+    let to_rgba = |x: &[u8]| -> Result<[u8; 4], TextureError> {
+        if header.is_indexed() {
+            let index = match size {
+                1 => x[0] as usize,
+                2 => le::read::<u16>(x, 0) as usize,
+                _ => return Err(TextureError::UnsupportedDepth(header.depth as u32)),
+            };
+            let entry = index.wrapping_sub(header.cmap_first as usize);
+            colormap
+                .get(entry)
+                .copied()
+                .ok_or(TextureError::BadIndex(index))
+        } else if header.is_greyscale() {
+            match size {
+                1 => Ok([x[0], x[0], x[0], 255]),
+                2 => Ok([x[0], x[0], x[0], x[1]]),
+                _ => Err(TextureError::UnsupportedDepth(header.depth as u32)),
+            }
+        } else {
+            color(x, alpha)
+        }
+    };
+
+    let width = header.width as u32;
+    let height = header.height as u32;
+    let mut ret = RgbaImage::new(width, height);
+    for (i, x) in pixels.chunks_exact(size).enumerate() {
+        let (x_pos, y_pos) = header.position(i);
+        ret.set_pixel(x_pos, y_pos, to_rgba(x)?);
+    }
+
+    // Plenty of writers leave alpha bits at 0 and the channel empty, that's not meant as invisible
+    if !alpha && size == 4 && ret.data.chunks_exact(4).all(|x| x[3] == 0) {
+        for x in ret.data.chunks_exact_mut(4) {
+            x[3] = 255;
+        }
+    }
+    Ok(ret)
+}
+
+fn encode_pixel(header: &TgaHeader, colormap: &[[u8; 4]], rgba: [u8; 4], out: &mut Vec<u8>) {
+    let [r, g, b, a] = rgba;
+    let size = header.pixel_size();
+    if header.is_indexed() {
+        let index = (nearest(colormap, rgba) + header.cmap_first as usize) as u16;
+        out.extend_from_slice(&index.to_le_bytes()[..size]);
+    } else if header.is_greyscale() {
+        out.push(luminance(rgba));
+        if size == 2 {
+            out.push(a);
+        }
+    } else {
+        match size {
+            2 => {
+                let mut v = ((r as u16 >> 3) << 10) | ((g as u16 >> 3) << 5) | (b as u16 >> 3);
+                if a >= 128 {
+                    v |= 0x8000;
+                }
+                le::push(out, v);
+            }
+            3 => out.extend_from_slice(&[b, g, r]),
+            _ => out.extend_from_slice(&[b, g, r, a]),
+        }
+    }
+}
+
+// Runs of 2+ become repeat packets, packets never cross scanlines
+fn rle_encode(pixels: &[u8], size: usize, width: usize, out: &mut Vec<u8>) {
+    for row in pixels.chunks(width.max(1) * size) {
+        let px: Vec<&[u8]> = row.chunks_exact(size).collect();
+        let mut i = 0;
+        while i < px.len() {
+            let mut run = 1;
+            while i + run < px.len() && run < 128 && px[i + run] == px[i] {
+                run += 1;
+            }
+            if run > 1 {
+                out.push(0x80 | (run - 1) as u8);
+                out.extend_from_slice(px[i]);
+                i += run;
+                continue;
+            }
+            let start = i;
+            while i < px.len() && i - start < 128 && !(i + 1 < px.len() && px[i + 1] == px[i]) {
+                i += 1;
+            }
+            out.push((i - start - 1) as u8);
+            for p in &px[start..i] {
+                out.extend_from_slice(p);
+            }
+        }
+    }
+}
+
+/// Encodes `image` in the exact format of `original`, keeping its id, colormap and trailer.
+/// Pixels that didn't change keep their original bytes.
+pub fn encode(image: &RgbaImage, original: &[u8]) -> Result<Vec<u8>, TextureError> {
+    let mut header = TgaHeader::read(original)?;
+    header.check()?;
+    let old_image = decode(original)?;
+    let (old_pixels, end) = unpack(&header, original)?;
+    let colormap = read_colormap(&header, original)?;
+    let same_size = old_image.width == image.width && old_image.height == image.height;
+    if image.width > u16::MAX as u32 || image.height > u16::MAX as u32 {
+        return Err(TextureError::TooLarge(image.width, image.height));
+    }
+    header.width = image.width as u16;
+    header.height = image.height as u16;
+
+    let size = header.pixel_size();
+    let count = image.width as usize * image.height as usize;
+    let mut pixels = Vec::with_capacity(count * size);
+    for i in 0..count {
+        let (x, y) = header.position(i);
+        let rgba = image.pixel(x, y);
+        if same_size && old_image.pixel(x, y) == rgba {
+            pixels.extend_from_slice(&old_pixels[i * size..i * size + size]);
+        } else {
+            encode_pixel(&header, &colormap, rgba, &mut pixels);
+        }
+    }
+
+    let mut ret = original[..header.data_offset()].to_vec();
+    le::write(&mut ret, 12, header.width);
+    le::write(&mut ret, 14, header.height);
+    if header.is_rle() {
+        rle_encode(&pixels, size, image.width as usize, &mut ret);
+    } else {
+        ret.extend_from_slice(&pixels);
+    }
+
+    // TGA 2.0 footer points at the extension and developer areas by absolute offset
+    let delta = ret.len() as i64 - end as i64;
+    let trailer = &original[end..];
+    ret.extend_from_slice(trailer);
+    let len = ret.len();
+    if trailer.len() >= 26 && trailer.ends_with(b"TRUEVISION-XFILE.\0") {
+        for at in [len - 26, len - 22] {
+            let offset = le::read::<u32>(&ret, at);
+            if offset != 0 {
+                let offset = (offset as i64 + delta) as u32;
+                le::write(&mut ret, at, offset);
+            }
+        }
+    }
+    Ok(ret)
+}
+
+/// Checks that decoding and encoding an untouched file gives back the same bytes, and that
+/// every pixel survives the encoder when it can't just copy the original bytes.
+pub fn verify_roundtrip(src: &[u8]) -> Result<(), TextureError> {
+    super::verify_roundtrip(src, decode, encode)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::texture::bmp;
+
+    // Made from testdata/python.bmp: 24 bit bottom to top, 32 bit RLE top to bottom with an id
+    // and a TGA 2.0 footer, 8 bit indices into a 32 bit colormap
+    const FIXTURES: [&[u8]; 3] = [
+        include_bytes!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/testdata/python_24.tga"
+        )),
+        include_bytes!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/testdata/python_rle.tga"
+        )),
+        include_bytes!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/testdata/python_mapped.tga"
+        )),
+    ];
+
+    #[test]
+    fn fixtures_decode_like_the_bmp() {
+        let bmp = bmp::decode(include_bytes!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/testdata/python.bmp"
+        )))
+        .unwrap();
+        for (i, src) in FIXTURES.iter().enumerate() {
+            let mut expected = bmp.clone();
+            if i == 0 {
+                for x in expected.data.chunks_exact_mut(4) {
+                    x[3] = 255;
+                }
+            }
+            assert_eq!(decode(src).unwrap(), expected, "fixture {i}");
+        }
+    }
+
+    #[test]
+    fn fixtures_roundtrip() {
+        for (i, src) in FIXTURES.iter().enumerate() {
+            verify_roundtrip(src).unwrap();
+            // every pixel encoded from scratch still gives the same file
+            let image = decode(src).unwrap();
+            let blank = encode(&RgbaImage::new(image.width, image.height), src).unwrap();
+            assert_ne!(&blank[..], &src[..], "fixture {i}");
+            assert_eq!(encode(&image, &blank).unwrap(), *src, "fixture {i}");
+        }
+    }
+
+    #[test]
+    fn empty_image_encodes() {
+        for src in FIXTURES {
+            let encoded = encode(&RgbaImage::new(0, 0), src).unwrap();
+            let image = decode(&encoded).unwrap();
+            assert_eq!((image.width, image.height), (0, 0));
+        }
+    }
+
+    // 2x1 image indexing a 2 entry 16 bit colormap, the second entry has its top bit set
+    fn mapped_16(alpha_bits: u8) -> Vec<u8> {
+        let mut ret = vec![
+            0, 1, 1, 0, 0, 2, 0, 16, 0, 0, 0, 0, 2, 0, 1, 0, 8, alpha_bits,
+        ];
+        ret.extend([0x1F, 0x00, 0x00, 0xFC]);
+        ret.extend([0, 1]);
+        ret
+    }
+
+    #[test]
+    fn colormap_alpha_follows_descriptor() {
+        let image = decode(&mapped_16(0)).unwrap();
+        assert_eq!(image.pixel(0, 0), [0, 0, 255, 255]);
+        assert_eq!(image.pixel(1, 0), [255, 0, 0, 255]);
+
+        let image = decode(&mapped_16(1)).unwrap();
+        assert_eq!(image.pixel(0, 0), [0, 0, 255, 0]);
+        assert_eq!(image.pixel(1, 0), [255, 0, 0, 255]);
+
+        let mut src = mapped_16(1);
+        src[7] = 15;
+        assert_eq!(decode(&src).unwrap().pixel(0, 0)[3], 255);
+    }
+
+    #[test]
+    fn huge_rle_header_is_rejected() {
+        // 65535x65535 32 bit RLE with a single packet behind it
+        let mut src = vec![
+            0, 0, 10, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xFF, 0xFF, 0xFF, 0xFF, 32, 8,
+        ];
+        src.extend([0xFF, 1, 2, 3, 4]);
+        let header = TgaHeader::read(&src).unwrap();
+        assert!(matches!(
+            read_pixels(&header, &src),
+            Err(TextureError::TooSmall(_))
+        ));
+        assert!(decode(&src).is_err());
+    }
+}